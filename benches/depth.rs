@@ -0,0 +1,140 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use depth_compare::binance::{BinanceSource, Market};
+use depth_compare::deep::{BinanceSnapshot, BinanceSpotOrderBookSnapshot, DepthRow, Event, LevelEvent, Shared};
+use depth_compare::source::{apply_update, OrderBookSource, SourceMessage};
+
+/// Captured `bnbbtc@depth@100ms` message
+const DEPTH_UPDATE: &str = include_str!("data/depth_update.json");
+/// Captured `bnbbtc@depth20@100ms` message
+const DEPTH20: &str = include_str!("data/depth20.json");
+
+const MID: f64 = 0.0142625;
+const TICK: f64 = 0.000001;
+
+/// Snapshot with `levels` bids and asks around `MID`
+fn snapshot(levels: usize) -> BinanceSnapshot {
+    let bids = (0..levels)
+        .map(|i| DepthRow { price: MID - (i as f64 + 0.5) * TICK, amount: 1.0 })
+        .collect();
+    let asks = (0..levels)
+        .map(|i| DepthRow { price: MID + (i as f64 + 0.5) * TICK, amount: 1.0 })
+        .collect();
+    BinanceSnapshot { last_update_id: 2861806778, bids, asks }
+}
+
+fn parsing(c: &mut Criterion) {
+    c.bench_function("depth_row/deserialize", |b| {
+        b.iter(|| serde_json::from_str::<DepthRow>(black_box(r#"["0.01426200","6.04200000"]"#)).unwrap())
+    });
+
+    c.bench_function("event/parse", |b| {
+        b.iter(|| serde_json::from_str::<Event>(black_box(DEPTH_UPDATE)).unwrap())
+    });
+
+    c.bench_function("level_event/parse", |b| {
+        b.iter(|| serde_json::from_str::<LevelEvent>(black_box(DEPTH20)).unwrap())
+    });
+}
+
+fn book_maintenance(c: &mut Criterion) {
+    for levels in [1000, 5000] {
+        let snapshot = snapshot(levels);
+        c.bench_with_input(BenchmarkId::new("shared/load_snapshot", levels), &snapshot, |b, snapshot| {
+            let mut shared = Shared::new();
+            b.iter(|| shared.load_snapshot(snapshot))
+        });
+
+        let mut shared = Shared::new();
+        shared.load_snapshot(&snapshot);
+        c.bench_with_input(BenchmarkId::new("shared/get_snapshot", levels), &shared, |b, shared| {
+            b.iter(|| shared.get_snapshot())
+        });
+    }
+
+    let event: Event = serde_json::from_str(DEPTH_UPDATE).unwrap();
+    let mut shared = Shared::new();
+    shared.load_snapshot(&snapshot(1000));
+    c.bench_function("shared/add_event", |b| {
+        b.iter_batched(|| event.clone(), |event| shared.add_event(event), BatchSize::SmallInput)
+    });
+}
+
+/// Owned `Event` against the borrowed `add_event_text` path, from text to updated book,
+/// then the same through `BinanceSource` as the sync engine runs it: `parse` and
+/// `apply_update` against `parse_owned` and `apply_text`, sequencing included
+fn parse_and_apply(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_and_apply");
+    let mut shared = Shared::new();
+    shared.load_snapshot(&snapshot(1000));
+
+    group.bench_function("owned", |b| {
+        b.iter(|| {
+            let event: Event = serde_json::from_str(black_box(DEPTH_UPDATE)).unwrap();
+            shared.add_event(event);
+        })
+    });
+
+    group.bench_function("borrowed", |b| {
+        b.iter(|| shared.add_event_text(black_box(DEPTH_UPDATE)).unwrap().last_update_id)
+    });
+
+    // A fresh book at the snapshot each time so the update follows it,
+    // returned to be dropped outside the timing
+    let source = BinanceSource::new(Market::Spot, "bnbbtc");
+    let book = || {
+        let mut shared = Shared::new();
+        shared.load_snapshot(&snapshot(1000));
+        shared
+    };
+
+    group.bench_function("source/owned", |b| {
+        b.iter_batched(book, |mut shared| {
+            match source.parse(black_box(DEPTH_UPDATE)).unwrap() {
+                Some(SourceMessage::Update(update)) => apply_update(&source, &mut shared, update, false, None).unwrap(),
+                _ => unreachable!(),
+            };
+            shared
+        }, BatchSize::SmallInput)
+    });
+
+    // The stream hands over an owned `String`, copied outside the timing
+    group.bench_function("source/text", |b| {
+        b.iter_batched(|| (book(), DEPTH_UPDATE.to_string()), |(mut shared, text)| {
+            match source.parse_owned(black_box(text)).unwrap() {
+                Some(SourceMessage::Text(text)) => source.apply_text(&mut shared, &text, false, None).unwrap(),
+                _ => unreachable!(),
+            };
+            shared
+        }, BatchSize::SmallInput)
+    });
+
+    group.finish();
+}
+
+fn comparison(c: &mut Criterion) {
+    let mut shared = Shared::new();
+    shared.load_snapshot(&snapshot(1000));
+    let depth = shared.get_snapshot();
+
+    // depth20 view of the same book with a few stale levels
+    let mut depth_level = BinanceSpotOrderBookSnapshot {
+        last_update_id: depth.last_update_id,
+        time_stamp: depth.time_stamp,
+        bids: depth.bids[..20].to_vec(),
+        asks: depth.asks[..20].to_vec(),
+    };
+    for row in depth_level.bids.iter_mut().step_by(5) {
+        row.amount += 1.0;
+    }
+
+    c.bench_function("snapshot/if_contains", |b| {
+        b.iter(|| depth.if_contains(black_box(&depth_level)))
+    });
+
+    c.bench_function("snapshot/find_different", |b| {
+        b.iter(|| depth.find_different(black_box(&depth_level)))
+    });
+}
+
+criterion_group!(benches, parsing, book_maintenance, parse_and_apply, comparison);
+criterion_main!(benches);
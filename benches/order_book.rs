@@ -0,0 +1,72 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use depth_compare::book::{OrderBook, VecBook};
+use depth_compare::deep::{BinanceSnapshot, DepthRow, Shared};
+
+const LEVELS: usize = 1000;
+const MID: f64 = 100.0;
+const TICK: f64 = 0.01;
+
+/// Snapshot with `LEVELS` bids and asks around `MID`
+fn snapshot() -> BinanceSnapshot {
+    let bids = (1..=LEVELS)
+        .map(|i| DepthRow { price: MID - i as f64 * TICK, amount: 1.0 })
+        .collect();
+    let asks = (1..=LEVELS)
+        .map(|i| DepthRow { price: MID + i as f64 * TICK, amount: 1.0 })
+        .collect();
+    BinanceSnapshot { last_update_id: 1, bids, asks }
+}
+
+/// Updates hit the top 20 levels, like most of the real diff stream
+fn near_touch(i: usize) -> f64 {
+    (i % 20 + 1) as f64 * TICK
+}
+
+fn bench_book<B: OrderBook>(c: &mut Criterion, name: &str, new_book: fn() -> B) {
+    let snapshot = snapshot();
+    let mut book = new_book();
+    book.load_snapshot(&snapshot);
+
+    c.bench_function(&format!("{}/update_existing", name), |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i += 1;
+            book.update_bid(MID - near_touch(i), black_box(2.0));
+            book.update_ask(MID + near_touch(i), black_box(2.0));
+        })
+    });
+
+    c.bench_function(&format!("{}/insert_remove", name), |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i += 1;
+            // Half a tick away, always a new level
+            let offset = near_touch(i) - TICK / 2.0;
+            book.update_bid(MID - offset, 1.0);
+            book.update_ask(MID + offset, 1.0);
+            book.update_bid(MID - offset, 0.0);
+            book.update_ask(MID + offset, 0.0);
+        })
+    });
+
+    for n in [1, 20, 100] {
+        c.bench_with_input(BenchmarkId::new(format!("{}/top_n", name), n), &n, |b, &n| {
+            b.iter(|| (book.top_bids(n), book.top_asks(n)))
+        });
+    }
+
+    c.bench_function(&format!("{}/get_snapshot", name), |b| {
+        b.iter(|| book.get_snapshot())
+    });
+}
+
+fn shared(c: &mut Criterion) {
+    bench_book(c, "shared", Shared::new);
+}
+
+fn vec_book(c: &mut Criterion) {
+    bench_book(c, "vec_book", VecBook::new);
+}
+
+criterion_group!(benches, shared, vec_book);
+criterion_main!(benches);
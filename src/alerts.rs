@@ -0,0 +1,324 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use tracing::warn;
+use crate::deep::{BinanceSpotOrderBookSnapshot, DepthRow};
+
+/// Condition on the comparison of a book with a reference book
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertRule {
+    /// Top `depth` levels of a side differ for more than `checks` consecutive checks
+    LevelsDiffer { depth: usize, checks: usize },
+    /// Best bid differs for longer than `over`
+    BestBidMismatch { over: Duration },
+    /// Best ask differs for longer than `over`
+    BestAskMismatch { over: Duration },
+}
+
+impl AlertRule {
+    /// Name of the rule in alerts, also its spec
+    pub fn name(&self) -> String {
+        match self {
+            AlertRule::LevelsDiffer { depth, checks } => format!("levels:{}:{}", depth, checks),
+            AlertRule::BestBidMismatch { over } => format!("best_bid:{}", over.as_millis()),
+            AlertRule::BestAskMismatch { over } => format!("best_ask:{}", over.as_millis()),
+        }
+    }
+
+    fn holds(&self, reference: &BinanceSpotOrderBookSnapshot, book: &BinanceSpotOrderBookSnapshot) -> bool {
+        let top = |rows: &[DepthRow], depth: usize| rows[..rows.len().min(depth)].to_vec();
+        match *self {
+            AlertRule::LevelsDiffer { depth, .. } => {
+                top(&reference.bids, depth) != top(&book.bids, depth) || top(&reference.asks, depth) != top(&book.asks, depth)
+            },
+            AlertRule::BestBidMismatch { .. } => reference.bids.first() != book.bids.first(),
+            AlertRule::BestAskMismatch { .. } => reference.asks.first() != book.asks.first(),
+        }
+    }
+
+    /// Whether a condition holding for `checks` checks since `since` fires
+    fn breached(&self, checks: usize, since: i64, now: i64) -> bool {
+        match *self {
+            AlertRule::LevelsDiffer { checks: threshold, .. } => checks > threshold,
+            AlertRule::BestBidMismatch { over } | AlertRule::BestAskMismatch { over } => now - since > over.as_millis() as i64,
+        }
+    }
+}
+
+/// Read `levels:<depth>:<checks>`, `best_bid:<ms>` or `best_ask:<ms>`
+impl FromStr for AlertRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let number = |text: &str| text.parse::<u64>().map_err(|_| anyhow!("Bad number {:?} in alert rule {:?}", text, s));
+        match parts[..] {
+            ["levels", depth, checks] => Ok(AlertRule::LevelsDiffer { depth: number(depth)? as usize, checks: number(checks)? as usize }),
+            ["best_bid", ms] => Ok(AlertRule::BestBidMismatch { over: Duration::from_millis(number(ms)?) }),
+            ["best_ask", ms] => Ok(AlertRule::BestAskMismatch { over: Duration::from_millis(number(ms)?) }),
+            _ => Err(anyhow!("Unknown alert rule {:?}, expect levels:<depth>:<checks>, best_bid:<ms> or best_ask:<ms>", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    Firing,
+    /// The condition of a fired alert cleared
+    Resolved,
+}
+
+/// Notification sent to the sinks, once when a rule fires and once when it recovers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    pub status: AlertStatus,
+    pub symbol: String,
+    /// Time the condition started holding, in ms
+    pub since: i64,
+    /// Time of the check that sent the alert, in ms
+    pub time_stamp: i64,
+    /// Consecutive checks the condition held, up to the last one for `Resolved`
+    pub checks: usize,
+}
+
+/// Where alerts go
+pub trait AlertSink: Send {
+    fn send(&mut self, alert: &Alert) -> io::Result<()>;
+}
+
+fn json_line(alert: &Alert) -> String {
+    let mut line = serde_json::to_string(alert).expect("Alert is serializable");
+    line.push('\n');
+    line
+}
+
+pub struct StderrSink;
+
+impl AlertSink for StderrSink {
+    fn send(&mut self, alert: &Alert) -> io::Result<()> {
+        io::stderr().write_all(json_line(alert).as_bytes())
+    }
+}
+
+/// Append alerts to a file as JSON lines
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path.into())?;
+        Ok(FileSink { file })
+    }
+}
+
+impl AlertSink for FileSink {
+    fn send(&mut self, alert: &Alert) -> io::Result<()> {
+        self.file.write_all(json_line(alert).as_bytes())
+    }
+}
+
+/// POST alerts as JSON to a local webhook, in the background
+/// so checks don't wait for it. Needs a tokio runtime.
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str) -> Self {
+        WebhookSink { client: reqwest::Client::new(), url: url.to_string() }
+    }
+}
+
+impl AlertSink for WebhookSink {
+    fn send(&mut self, alert: &Alert) -> io::Result<()> {
+        let request = self.client.post(&self.url).json(alert);
+        let url = self.url.clone();
+        tokio::spawn(async move {
+            match request.send().await {
+                Ok(response) if response.status().is_success() => (),
+                Ok(response) => warn!(%url, status = response.status().as_u16(), "webhook refused alert"),
+                Err(e) => warn!(%url, error = %e, "fail to post alert"),
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Write alerts as JSON lines to a Unix socket, connecting for each alert
+/// so the listener may restart
+#[cfg(unix)]
+pub struct UnixSocketSink {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixSocketSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        UnixSocketSink { path: path.into() }
+    }
+}
+
+#[cfg(unix)]
+impl AlertSink for UnixSocketSink {
+    fn send(&mut self, alert: &Alert) -> io::Result<()> {
+        UnixStream::connect(&self.path)?.write_all(json_line(alert).as_bytes())
+    }
+}
+
+/// Run a blocking sink on its own thread so checks don't wait for it,
+/// alerts are sent in order and failures are logged there
+pub struct BackgroundSink {
+    alerts: Sender<Alert>,
+}
+
+impl BackgroundSink {
+    pub fn new(mut sink: impl AlertSink + 'static) -> Self {
+        let (alerts, received) = mpsc::channel::<Alert>();
+        thread::spawn(move || {
+            for alert in received {
+                if let Err(e) = sink.send(&alert) {
+                    warn!(rule = %alert.rule, error = %e, "fail to send alert");
+                }
+            }
+        });
+        BackgroundSink { alerts }
+    }
+}
+
+impl AlertSink for BackgroundSink {
+    fn send(&mut self, alert: &Alert) -> io::Result<()> {
+        self.alerts.send(alert.clone()).map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "alert thread stopped"))
+    }
+}
+
+/// Build a sink from `stderr`, `file:<path>`, `webhook:<url>` or `unix:<path>`,
+/// file and socket writes happen in the background
+pub fn sink_from_spec(spec: &str) -> Result<Box<dyn AlertSink>> {
+    match spec.split_once(':') {
+        None if spec == "stderr" => Ok(Box::new(StderrSink)),
+        Some(("file", path)) => Ok(Box::new(BackgroundSink::new(FileSink::open(path)?))),
+        Some(("webhook", url)) => Ok(Box::new(WebhookSink::new(url))),
+        #[cfg(unix)]
+        Some(("unix", path)) => Ok(Box::new(BackgroundSink::new(UnixSocketSink::new(path)))),
+        _ => Err(anyhow!("Unknown alert sink {:?}, expect stderr, file:<path>, webhook:<url> or unix:<path>", spec)),
+    }
+}
+
+/// State of a rule between checks
+struct RuleState {
+    rule: AlertRule,
+    /// Consecutive checks the condition held
+    checks: usize,
+    since: i64,
+    firing: bool,
+}
+
+/// Evaluate alert rules on every comparison and notify the sinks when a rule
+/// fires or recovers. A firing rule sends nothing more until it recovers.
+pub struct Alerter {
+    symbol: String,
+    rules: Vec<RuleState>,
+    sinks: Vec<Box<dyn AlertSink>>,
+}
+
+impl Alerter {
+    pub fn new(symbol: &str) -> Self {
+        Alerter { symbol: symbol.to_string(), rules: Vec::new(), sinks: Vec::new() }
+    }
+
+    pub fn add_rule(&mut self, rule: AlertRule) {
+        self.rules.push(RuleState { rule, checks: 0, since: 0, firing: false });
+    }
+
+    pub fn add_sink(&mut self, sink: Box<dyn AlertSink>) {
+        self.sinks.push(sink);
+    }
+
+    /// Check `book` against `reference` at `now` (ms), return the alerts sent
+    pub fn check(&mut self, reference: &BinanceSpotOrderBookSnapshot, book: &BinanceSpotOrderBookSnapshot, now: i64) -> Vec<Alert> {
+        let mut alerts = Vec::new();
+        for state in &mut self.rules {
+            let held = state.checks;
+            let status = if state.rule.holds(reference, book) {
+                if state.checks == 0 {
+                    state.since = now;
+                }
+                state.checks += 1;
+                let fire = !state.firing && state.rule.breached(state.checks, state.since, now);
+                state.firing |= fire;
+                fire.then_some(AlertStatus::Firing)
+            } else {
+                let resolved = state.firing;
+                state.firing = false;
+                state.checks = 0;
+                resolved.then_some(AlertStatus::Resolved)
+            };
+            if let Some(status) = status {
+                alerts.push(Alert {
+                    rule: state.rule.name(),
+                    status,
+                    symbol: self.symbol.clone(),
+                    since: state.since,
+                    time_stamp: now,
+                    checks: state.checks.max(held),
+                });
+            }
+        }
+
+        for alert in &alerts {
+            for sink in &mut self.sinks {
+                if let Err(e) = sink.send(alert) {
+                    warn!(rule = %alert.rule, error = %e, "fail to send alert");
+                }
+            }
+        }
+        alerts
+    }
+}
+
+#[test]
+fn alerts_fire_once_and_recover(){
+    let snapshot = |bid: f64| BinanceSpotOrderBookSnapshot::from_levels(&[(bid, 1.0), (9.0, 1.0)], &[(11.0, 1.0)]);
+    let path = std::env::temp_dir().join(format!("depth_compare_alerts_{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let mut alerter = Alerter::new("bnbbtc");
+    alerter.add_rule("levels:5:3".parse().unwrap());
+    alerter.add_rule("best_bid:2000".parse().unwrap());
+    alerter.add_sink(Box::new(FileSink::open(&path).unwrap()));
+    assert!("levels:5".parse::<AlertRule>().is_err());
+    assert!(sink_from_spec("stderr").is_ok());
+    assert!(sink_from_spec("syslog").is_err());
+
+    let reference = snapshot(10.0);
+    let mut sent = Vec::new();
+    for (second, bid) in [10.0, 9.5, 9.5, 9.5, 9.5, 9.5, 10.0].into_iter().enumerate() {
+        for alert in alerter.check(&reference, &snapshot(bid), second as i64 * 1000) {
+            sent.push((second, alert.rule, alert.status));
+        }
+    }
+
+    assert_eq!(sent, vec![
+        // Differing since second 1, over 2s at second 4
+        (4, "levels:5:3".to_string(), AlertStatus::Firing),
+        (4, "best_bid:2000".to_string(), AlertStatus::Firing),
+        (6, "levels:5:3".to_string(), AlertStatus::Resolved),
+        (6, "best_bid:2000".to_string(), AlertStatus::Resolved),
+    ]);
+    let lines = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(lines.lines().count(), 4);
+    assert!(lines.starts_with(r#"{"rule":"levels:5:3","status":"firing","symbol":"bnbbtc","since":1000,"time_stamp":4000,"checks":4}"#));
+    let _ = std::fs::remove_file(&path);
+}
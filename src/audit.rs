@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use ordered_float::OrderedFloat;
+use crate::deep::{BinanceSnapshot, BinanceSpotOrderBookSnapshot, DepthRow, Shared};
+use crate::source::{BookUpdate, OrderBookSource, Sequence};
+
+pub const DEFAULT_AUDIT_INTERVAL: Duration = Duration::from_secs(60);
+/// Levels of the depth20 book checked against ground truth
+pub const LEVEL_AUDIT_DEPTH: usize = 20;
+
+/// Level whose amount differs from ground truth, `None` for a missing level
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelDiff {
+    pub price: f64,
+    /// Amount in the REST snapshot
+    pub expected: Option<f64>,
+    /// Amount in the live book
+    pub found: Option<f64>,
+}
+
+/// Differences of a live book from ground truth at `update_id`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookDiff {
+    pub update_id: i64,
+    pub bids: Vec<LevelDiff>,
+    pub asks: Vec<LevelDiff>,
+}
+
+impl BookDiff {
+    /// Compare the best `depth` levels of `truth` with the levels of `book`
+    /// in the same price range, deeper levels of `book` are ignored
+    pub fn between(truth: &BinanceSpotOrderBookSnapshot, book: &BinanceSpotOrderBookSnapshot, depth: usize) -> Self {
+        BookDiff {
+            update_id: truth.last_update_id,
+            bids: side_diff(&truth.bids, &book.bids, depth, |price, bound| price >= bound),
+            asks: side_diff(&truth.asks, &book.asks, depth, |price, bound| price <= bound),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    /// Number of differing levels
+    pub fn len(&self) -> usize {
+        self.bids.len() + self.asks.len()
+    }
+}
+
+/// `within(price, bound)` tells whether a level is at or better than `bound`
+fn side_diff(truth: &[DepthRow], book: &[DepthRow], depth: usize, within: fn(f64, f64) -> bool) -> Vec<LevelDiff> {
+    let truth = &truth[..truth.len().min(depth)];
+    let bound = truth.last().map(|row| row.price);
+    let mut levels: BTreeMap<OrderedFloat<f64>, LevelDiff> = BTreeMap::new();
+    for row in truth {
+        levels.insert(OrderedFloat(row.price), LevelDiff { price: row.price, expected: Some(row.amount), found: None });
+    }
+    for row in book.iter().filter(|row| bound.is_none_or(|bound| within(row.price, bound))) {
+        levels.entry(OrderedFloat(row.price))
+            .or_insert(LevelDiff { price: row.price, expected: None, found: None })
+            .found = Some(row.amount);
+    }
+    levels.into_values().filter(|level| level.expected != level.found).collect()
+}
+
+/// Outcome of one audit, a diff is `None` when the book had no version
+/// at an update id ground truth went through
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditReport {
+    /// `lastUpdateId` of the REST snapshot
+    pub snapshot_id: i64,
+    /// Diff book against ground truth
+    pub depth: Option<BookDiff>,
+    /// Depth20 book against ground truth
+    pub level: Option<BookDiff>,
+}
+
+/// Roll the REST `snapshot` forward through the buffered `updates` of `source`
+/// until `book_at` has a version of the live book at the same update id,
+/// then diff that version against it.
+/// `None` if the updates don't continue the snapshot or no version matches.
+pub fn audit<S: OrderBookSource>(
+    source: &S,
+    snapshot: &BinanceSnapshot,
+    updates: &[BookUpdate],
+    depth: usize,
+    book_at: impl Fn(i64) -> Option<BinanceSpotOrderBookSnapshot>,
+) -> Option<BookDiff> {
+    let mut truth = Shared::new();
+    truth.load_snapshot(snapshot);
+    let diff = |truth: &Shared| {
+        let book = book_at(truth.id()).filter(|book| book.last_update_id == truth.id())?;
+        Some(BookDiff::between(&truth.get_snapshot(), &book, depth))
+    };
+    if let Some(diff) = diff(&truth) {
+        return Some(diff)
+    }
+
+    let mut started = false;
+    for update in updates {
+        if !started {
+            if !source.match_snapshot(update, snapshot.last_update_id) {
+                continue
+            }
+            started = true;
+        } else if source.sequence(update, truth.id()) != Sequence::Apply {
+            return None
+        }
+        truth.apply_update(update.clone());
+        if let Some(diff) = diff(&truth) {
+            return Some(diff)
+        }
+    }
+    None
+}
+
+#[test]
+fn audit_rolls_snapshot_to_book_version(){
+    use crate::binance::BinanceSource;
+    use crate::connection::Market;
+    use crate::history::BookHistory;
+
+    let row = |price, amount| DepthRow { price, amount };
+    let update = |first, last, bids: Vec<DepthRow>| BookUpdate {
+        event_time: last,
+        first_update_id: first,
+        last_update_id: last,
+        previous_update_id: first - 1,
+        bids,
+        asks: vec![],
+        checksum: None,
+    };
+    let snapshot = BinanceSnapshot { last_update_id: 15, bids: vec![row(10.0, 1.0), row(9.0, 2.0)], asks: vec![row(11.0, 1.0)] };
+    let updates = vec![
+        update(11, 14, vec![row(10.0, 5.0)]),
+        update(15, 18, vec![row(10.0, 3.0)]),
+        update(19, 20, vec![row(9.5, 1.0)]),
+    ];
+
+    // The live book has versions at event boundaries, and lost the 9.5 bid
+    let mut history = BookHistory::new(4);
+    for (id, bids) in [(14, vec![row(10.0, 5.0), row(9.0, 2.0)]), (18, vec![row(10.0, 3.0), row(9.0, 2.0)]), (20, vec![row(10.0, 3.0), row(9.0, 2.0), row(8.0, 7.0)])] {
+        history.push(BinanceSpotOrderBookSnapshot { last_update_id: id, time_stamp: id, bids, asks: vec![row(11.0, 1.0)] });
+    }
+    let book_at = |id| history.at_update_id(id).cloned();
+
+    let source = BinanceSource::new(Market::Spot, "bnbbtc");
+    let diff = audit(&source, &snapshot, &updates, usize::MAX, book_at).unwrap();
+    // Ground truth caught up at 18 where both agree
+    assert_eq!(diff, BookDiff { update_id: 18, bids: vec![], asks: vec![] });
+
+    let diff = audit(&source, &snapshot, &updates, usize::MAX, |id| book_at(id).filter(|book| book.last_update_id == 20)).unwrap();
+    assert_eq!(diff.update_id, 20);
+    // 8.0 is deeper than ground truth
+    assert_eq!(diff.bids, vec![LevelDiff { price: 9.5, expected: Some(1.0), found: None }]);
+    assert!(audit(&source, &snapshot, &updates[2..], usize::MAX, book_at).is_none());
+}
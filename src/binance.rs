@@ -0,0 +1,277 @@
+use std::fmt;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+use tokio::sync::Mutex;
+use tracing::debug;
+use url::Url;
+use crate::deep::{BinanceSnapshot, DepthUpdate, Event, FuturesEvent, LevelEvent, Shared};
+use crate::error::DepthError;
+use crate::limiter::WeightLimiter;
+use crate::rest::SnapshotFetcher;
+use crate::source::{Applied, BookUpdate, OrderBookSource, SourceMessage};
+use crate::trades::TradeCorrelator;
+
+const STREAM: &str = "wss://stream.binance.com:9443/ws";
+const REST: &str = "https://api.binance.com/api/v3/depth";
+const USD_FUTURES_STREAM: &str = "wss://fstream.binance.com/ws";
+const USD_FUTURES_REST: &str = "https://fapi.binance.com/fapi/v1/depth";
+const COIN_FUTURES_STREAM: &str = "wss://dstream.binance.com/ws";
+const COIN_FUTURES_REST: &str = "https://dapi.binance.com/dapi/v1/depth";
+pub(crate) const COIN_FUTURES_EXCHANGE_INFO: &str = "https://dapi.binance.com/dapi/v1/exchangeInfo";
+pub(crate) const DEPTH_STREAM: &str = "depth@100ms";
+pub(crate) const LEVEL_DEPTH_STREAM: &str = "depth20@100ms";
+pub(crate) const BOOK_TICKER_STREAM: &str = "bookTicker";
+
+/// Binance market an order book belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Market {
+    Spot,
+    /// USD-M futures (fapi)
+    UsdFutures,
+    /// COIN-M futures (dapi), symbols like "BTCUSD_PERP" or "BTCUSD_250627".
+    /// Quantities are in contracts of a fixed USD value, see `contract_size`
+    CoinFutures,
+}
+
+impl Market {
+    fn stream_base(self) -> &'static str {
+        match self {
+            Market::Spot => STREAM,
+            Market::UsdFutures => USD_FUTURES_STREAM,
+            Market::CoinFutures => COIN_FUTURES_STREAM,
+        }
+    }
+
+    fn rest_base(self) -> &'static str {
+        match self {
+            Market::Spot => REST,
+            Market::UsdFutures => USD_FUTURES_REST,
+            Market::CoinFutures => COIN_FUTURES_REST,
+        }
+    }
+
+    /// Request weight of a depth snapshot with limit 1000
+    fn rest_weight(self) -> u32 {
+        match self {
+            Market::Spot => 50,
+            Market::UsdFutures | Market::CoinFutures => 20,
+        }
+    }
+
+    fn limiter(self) -> &'static WeightLimiter {
+        match self {
+            Market::Spot => WeightLimiter::global(),
+            Market::UsdFutures => WeightLimiter::usd_futures(),
+            Market::CoinFutures => WeightLimiter::coin_futures(),
+        }
+    }
+
+    /// `stream` as labelled in metrics, prefixed out of spot
+    /// so the same symbol on two markets doesn't collide
+    pub(crate) fn stream_label(self, stream: &str) -> String {
+        match self {
+            Market::Spot => stream.to_string(),
+            _ => format!("{}/{}", self, stream),
+        }
+    }
+}
+
+impl fmt::Display for Market {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Market::Spot => write!(f, "spot"),
+            Market::UsdFutures => write!(f, "usdm"),
+            Market::CoinFutures => write!(f, "coinm"),
+        }
+    }
+}
+
+impl FromStr for Market {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "spot" => Ok(Market::Spot),
+            "usdm" => Ok(Market::UsdFutures),
+            "coinm" => Ok(Market::CoinFutures),
+            _ => Err(anyhow!("Unknown market {:?}, expect spot, usdm or coinm", s)),
+        }
+    }
+}
+
+pub(crate) fn stream_url(market: Market, symbol: &str, stream: &str) -> Url {
+    Url::parse(&format!("{}/{}@{}", market.stream_base(), symbol, stream)).expect("Bad URL")
+}
+
+fn rest_url(market: Market, symbol: &str) -> String {
+    format!("{}?symbol={}&limit=1000", market.rest_base(), symbol.to_uppercase())
+}
+
+/// Depth20 message of `market` as a `LevelEvent`
+pub(crate) fn parse_level_event(market: Market, text: &str) -> serde_json::Result<LevelEvent> {
+    match market {
+        Market::Spot => serde_json::from_str(text),
+        // Futures partial depth is sent as `depthUpdate`
+        _ => serde_json::from_str::<FuturesEvent>(text).map(LevelEvent::from),
+    }
+}
+
+/// Normalize a diff depth event
+fn normalize<E: DepthUpdate>(text: &str) -> Result<BookUpdate, DepthError> {
+    let event: E = serde_json::from_str(text).map_err(|e| DepthError::parse(text, e))?;
+    let (event_time, first_update_id, last_update_id, previous_update_id) =
+        (event.event_time(), event.first_update_id(), event.last_update_id(), event.previous_update_id());
+    let event: Event = event.into();
+    Ok(BookUpdate {
+        event_time,
+        first_update_id,
+        last_update_id,
+        previous_update_id,
+        bids: event.bids,
+        asks: event.asks,
+        checksum: None,
+    })
+}
+
+/// Diff depth stream of a Binance market
+pub struct BinanceSource {
+    market: Market,
+    /// Lower case, as in stream names
+    symbol: String,
+    fetcher: Mutex<SnapshotFetcher>,
+}
+
+impl BinanceSource {
+    pub fn new(market: Market, symbol: &str) -> Self {
+        let symbol = symbol.to_lowercase();
+        let fetcher = SnapshotFetcher::with_limiter(&rest_url(market, &symbol), market.rest_weight(), market.limiter());
+        BinanceSource {
+            market,
+            symbol,
+            fetcher: Mutex::new(fetcher),
+        }
+    }
+}
+
+impl OrderBookSource for BinanceSource {
+    fn stream(&self) -> String {
+        self.market.stream_label(DEPTH_STREAM)
+    }
+
+    fn stream_url(&self) -> Url {
+        stream_url(self.market, &self.symbol, DEPTH_STREAM)
+    }
+
+    fn parse(&self, text: &str) -> Result<Option<SourceMessage>, DepthError> {
+        let update = match self.market {
+            Market::Spot => normalize::<Event>(text)?,
+            Market::UsdFutures | Market::CoinFutures => normalize::<FuturesEvent>(text)?,
+        };
+        Ok(Some(SourceMessage::Update(update)))
+    }
+
+    /// Spot levels are applied straight from the text, read once by `apply_text`
+    fn parse_owned(&self, text: String) -> Result<Option<SourceMessage>, DepthError> {
+        match self.market {
+            Market::Spot => Ok(Some(SourceMessage::Text(text))),
+            Market::UsdFutures | Market::CoinFutures => self.parse(&text),
+        }
+    }
+
+    /// The ids are read before the levels, which are only applied if the update follows the book
+    fn apply_text(
+        &self,
+        book: &mut Shared,
+        text: &str,
+        first: bool,
+        trades: Option<&mut TradeCorrelator>,
+    ) -> Result<Option<Applied>, DepthError> {
+        let book_id = book.id();
+        let sequence = |first_update_id: i64, last_update_id: i64| {
+            let update = BookUpdate {
+                event_time: 0,
+                first_update_id,
+                last_update_id,
+                previous_update_id: first_update_id - 1,
+                bids: Vec::new(),
+                asks: Vec::new(),
+                checksum: None,
+            };
+            if first { self.bootstrap(&update, book_id) } else { self.sequence(&update, book_id) }
+        };
+        let (event, sequence) = book.apply_event_text(text, sequence, trades)?;
+        Ok(Some(Applied {
+            event_time: event.ts,
+            first_update_id: event.first_update_id,
+            last_update_id: event.last_update_id,
+            previous_update_id: event.first_update_id - 1,
+            sequence,
+        }))
+    }
+
+    async fn fetch_snapshot(&self) -> Result<BinanceSnapshot, DepthError> {
+        let mut fetcher = self.fetcher.lock().await;
+        let snapshot = fetcher.fetch().await?;
+        debug!(
+            snapshot_id = snapshot.last_update_id,
+            used_weight = fetcher.used_weight(),
+            weight_utilization = self.market.limiter().utilization(),
+            "fetched snapshot"
+        );
+        Ok(snapshot)
+    }
+
+    fn match_snapshot(&self, update: &BookUpdate, last_update_id: i64) -> bool {
+        match self.market {
+            // U <= lastUpdateId + 1 <= u
+            Market::Spot => update.first_update_id <= last_update_id + 1 && last_update_id < update.last_update_id,
+            // U <= lastUpdateId <= u
+            Market::UsdFutures | Market::CoinFutures => {
+                update.first_update_id <= last_update_id && last_update_id <= update.last_update_id
+            },
+        }
+    }
+}
+
+#[test]
+fn binance_sync_rules(){
+    use crate::source::Sequence;
+
+    let text = r#"{"e":"depthUpdate","E":1000,"T":999,"s":"BTCUSDT","U":10,"u":12,"pu":9,"b":[["9.5","1.0"]],"a":[]}"#;
+    let futures = BinanceSource::new(Market::UsdFutures, "BTCUSDT");
+    let spot = BinanceSource::new(Market::Spot, "BTCUSDT");
+    let update = match futures.parse(text).unwrap() {
+        Some(SourceMessage::Update(update)) => update,
+        other => panic!("Expect an update, found {:?}", other),
+    };
+    assert_eq!(Some(SourceMessage::Update(update.clone())), spot.parse(text).unwrap());
+    assert_eq!((update.event_time, update.previous_update_id), (1000, 9));
+
+    // Futures bootstrap accepts u == lastUpdateId, spot doesn't
+    assert!(futures.match_snapshot(&update, 12));
+    assert!(!spot.match_snapshot(&update, 12));
+    assert!(futures.match_snapshot(&update, 10));
+    assert!(!futures.match_snapshot(&update, 9));
+
+    assert_eq!(futures.sequence(&update, 9), Sequence::Apply);
+    assert_eq!(futures.sequence(&update, 12), Sequence::Skip);
+    assert_eq!(futures.sequence(&update, 8), Sequence::Gap);
+
+    // Spot messages keep their levels in the text until applied
+    let text = r#"{"e":"depthUpdate","E":1000,"s":"BTCUSDT","U":10,"u":12,"b":[["9.5","1.0"]],"a":[["9.6","2.0"]]}"#;
+    let text = match spot.parse_owned(text.to_string()).unwrap() {
+        Some(SourceMessage::Text(text)) => text,
+        other => panic!("Expect a text update, found {:?}", other),
+    };
+    let mut book = Shared::new();
+    book.load_snapshot(&BinanceSnapshot { last_update_id: 8, bids: vec![], asks: vec![] });
+    let applied = spot.apply_text(&mut book, &text, false, None).unwrap().unwrap();
+    assert_eq!((applied.event_time, applied.previous_update_id, applied.sequence), (1000, 9, Sequence::Gap));
+    assert_eq!(book.level_counts(), (0, 0));
+    // Older than a snapshot at 12, newer than one at 8
+    book.load_snapshot(&BinanceSnapshot { last_update_id: 12, bids: vec![], asks: vec![] });
+    assert_eq!(spot.apply_text(&mut book, &text, true, None).unwrap().unwrap().sequence, Sequence::Skip);
+    book.load_snapshot(&BinanceSnapshot { last_update_id: 11, bids: vec![], asks: vec![] });
+    assert_eq!(spot.apply_text(&mut book, &text, true, None).unwrap().unwrap().sequence, Sequence::Apply);
+    assert_eq!((book.id(), book.level_counts()), (12, (1, 1)));
+}
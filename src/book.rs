@@ -0,0 +1,200 @@
+use std::cmp::Ordering;
+use crate::deep::{BinanceSnapshot, BinanceSpotOrderBookSnapshot, DepthRow, Event, Shared};
+
+/// Operations shared by every order book implementation,
+/// a zero `amount` removes the level
+pub trait OrderBook {
+    /// return last_update_id
+    fn id(&self) -> i64;
+
+    fn load_snapshot(&mut self, snapshot: &BinanceSnapshot);
+
+    fn add_event(&mut self, event: Event);
+
+    fn update_bid(&mut self, price: f64, amount: f64);
+
+    fn update_ask(&mut self, price: f64, amount: f64);
+
+    fn best_bid(&self) -> Option<DepthRow>;
+
+    fn best_ask(&self) -> Option<DepthRow>;
+
+    /// Best `n` bids, highest price first
+    fn top_bids(&self, n: usize) -> Vec<DepthRow>;
+
+    /// Best `n` asks, lowest price first
+    fn top_asks(&self, n: usize) -> Vec<DepthRow>;
+
+    fn get_snapshot(&self) -> BinanceSpotOrderBookSnapshot;
+}
+
+impl OrderBook for Shared {
+    fn id(&self) -> i64 {
+        Shared::id(self)
+    }
+
+    fn load_snapshot(&mut self, snapshot: &BinanceSnapshot) {
+        Shared::load_snapshot(self, snapshot)
+    }
+
+    fn add_event(&mut self, event: Event) {
+        Shared::add_event(self, event)
+    }
+
+    fn update_bid(&mut self, price: f64, amount: f64) {
+        Shared::update_bid(self, price, amount)
+    }
+
+    fn update_ask(&mut self, price: f64, amount: f64) {
+        Shared::update_ask(self, price, amount)
+    }
+
+    fn best_bid(&self) -> Option<DepthRow> {
+        self.top_bids(1).pop()
+    }
+
+    fn best_ask(&self) -> Option<DepthRow> {
+        self.top_asks(1).pop()
+    }
+
+    fn top_bids(&self, n: usize) -> Vec<DepthRow> {
+        Shared::top_bids(self, n)
+    }
+
+    fn top_asks(&self, n: usize) -> Vec<DepthRow> {
+        Shared::top_asks(self, n)
+    }
+
+    fn get_snapshot(&self) -> BinanceSpotOrderBookSnapshot {
+        Shared::get_snapshot(self)
+    }
+}
+
+/// Order book kept in two sorted `Vec`s with the touch at the end,
+/// so the frequent updates near the best price move little memory.
+#[derive(Default)]
+pub struct VecBook {
+    last_update_id: i64,
+    time_stamp: i64,
+    /// Ascending, best bid last
+    bids: Vec<DepthRow>,
+    /// Descending, best ask last
+    asks: Vec<DepthRow>,
+}
+
+impl VecBook {
+    pub fn new() -> Self {
+        VecBook::default()
+    }
+
+    /// Insert, replace or (with zero `amount`) remove a level in `side`,
+    /// `order` tells how a stored price compares to the new one.
+    fn update_side(side: &mut Vec<DepthRow>, price: f64, amount: f64, order: impl Fn(f64) -> Ordering) {
+        match side.binary_search_by(|row| order(row.price)) {
+            Ok(index) if amount == 0.0 => {
+                side.remove(index);
+            },
+            Ok(index) => side[index].amount = amount,
+            Err(_) if amount == 0.0 => (),
+            Err(index) => side.insert(index, DepthRow { price, amount }),
+        }
+    }
+}
+
+impl OrderBook for VecBook {
+    fn id(&self) -> i64 {
+        self.last_update_id
+    }
+
+    fn load_snapshot(&mut self, snapshot: &BinanceSnapshot) {
+        self.bids.clear();
+        self.bids.extend(snapshot.bids.iter().filter(|row| row.amount != 0.0));
+        self.bids.sort_by(|a, b| a.price.total_cmp(&b.price));
+
+        self.asks.clear();
+        self.asks.extend(snapshot.asks.iter().filter(|row| row.amount != 0.0));
+        self.asks.sort_by(|a, b| b.price.total_cmp(&a.price));
+
+        self.last_update_id = snapshot.last_update_id;
+    }
+
+    fn add_event(&mut self, event: Event) {
+        for ask in event.asks {
+            self.update_ask(ask.price, ask.amount);
+        }
+
+        for bid in event.bids {
+            self.update_bid(bid.price, bid.amount);
+        }
+
+        self.last_update_id = event.last_update_id;
+        self.time_stamp = event.ts;
+    }
+
+    fn update_bid(&mut self, price: f64, amount: f64) {
+        Self::update_side(&mut self.bids, price, amount, |stored| stored.total_cmp(&price))
+    }
+
+    fn update_ask(&mut self, price: f64, amount: f64) {
+        Self::update_side(&mut self.asks, price, amount, |stored| price.total_cmp(&stored))
+    }
+
+    fn best_bid(&self) -> Option<DepthRow> {
+        self.bids.last().copied()
+    }
+
+    fn best_ask(&self) -> Option<DepthRow> {
+        self.asks.last().copied()
+    }
+
+    fn top_bids(&self, n: usize) -> Vec<DepthRow> {
+        self.bids.iter().rev().take(n).copied().collect()
+    }
+
+    fn top_asks(&self, n: usize) -> Vec<DepthRow> {
+        self.asks.iter().rev().take(n).copied().collect()
+    }
+
+    fn get_snapshot(&self) -> BinanceSpotOrderBookSnapshot {
+        BinanceSpotOrderBookSnapshot {
+            last_update_id: self.last_update_id,
+            time_stamp: self.time_stamp,
+            asks: self.top_asks(self.asks.len()),
+            bids: self.top_bids(self.bids.len()),
+        }
+    }
+}
+
+#[test]
+fn vec_book_matches_shared(){
+    let snapshot = BinanceSnapshot {
+        last_update_id: 10,
+        bids: vec![DepthRow{price: 9.0, amount: 1.0}, DepthRow{price: 9.5, amount: 2.0}],
+        asks: vec![DepthRow{price: 10.5, amount: 1.0}, DepthRow{price: 10.0, amount: 3.0}],
+    };
+    let event = Event {
+        ttype: "depthUpdate".to_string(),
+        ts: 1000,
+        pair: "BNBBTC".to_string(),
+        first_update_id: 11,
+        last_update_id: 12,
+        bids: vec![DepthRow{price: 9.5, amount: 0.0}, DepthRow{price: 9.7, amount: 4.0}],
+        asks: vec![DepthRow{price: 10.0, amount: 2.0}, DepthRow{price: 11.0, amount: 1.0}],
+    };
+
+    let mut shared = Shared::new();
+    let mut vec_book = VecBook::new();
+    OrderBook::load_snapshot(&mut shared, &snapshot);
+    vec_book.load_snapshot(&snapshot);
+    OrderBook::add_event(&mut shared, event.clone());
+    vec_book.add_event(event);
+
+    let expected = OrderBook::get_snapshot(&shared);
+    let found = vec_book.get_snapshot();
+    assert_eq!(found.bids, expected.bids);
+    assert_eq!(found.asks, expected.asks);
+    assert_eq!(found.last_update_id, 12);
+    assert_eq!(vec_book.best_bid(), Some(DepthRow{price: 9.7, amount: 4.0}));
+    assert_eq!(vec_book.best_ask(), Some(DepthRow{price: 10.0, amount: 2.0}));
+    assert_eq!(vec_book.top_asks(2), OrderBook::top_asks(&shared, 2));
+}
@@ -0,0 +1,148 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::Deserialize;
+use tokio::time::sleep;
+use tracing::{debug, warn};
+use crate::error::DepthError;
+use crate::limiter::WeightLimiter;
+use crate::metrics::Metrics;
+use crate::rest::status_error;
+
+const TIME_URL: &str = "https://api.binance.com/api/v3/time";
+const TIME_WEIGHT: u32 = 1;
+pub const DEFAULT_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+/// Recent samples the offset is estimated from
+const MAX_SAMPLES: usize = 8;
+
+static GLOBAL: OnceLock<ServerClock> = OnceLock::new();
+
+#[derive(Deserialize)]
+struct ServerTime {
+    #[serde(rename = "serverTime")]
+    server_time: i64,
+}
+
+/// Offset of the exchange clock from the local clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockOffset {
+    /// Add to local time to get exchange time, in ms
+    pub offset_ms: i64,
+    /// Half the round trip of the sample, the true offset is within +/- this
+    pub uncertainty_ms: i64,
+}
+
+impl ClockOffset {
+    /// NTP style estimate: the server read its clock `server_time`
+    /// at the midpoint of a request sent at `sent_at` and answered at `received_at` (local ms)
+    pub fn from_sample(sent_at: i64, server_time: i64, received_at: i64) -> Self {
+        let round_trip = (received_at - sent_at).max(0);
+        ClockOffset {
+            offset_ms: server_time - (sent_at + round_trip / 2),
+            uncertainty_ms: (round_trip + 1) / 2,
+        }
+    }
+}
+
+/// Local time in ms since the epoch, not corrected
+pub fn local_ms() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64
+}
+
+/// Local time corrected to the exchange clock, comparable with event time `E`
+pub fn now_ms() -> i64 {
+    ServerClock::global().now_ms()
+}
+
+/// Estimate of the exchange clock from periodic samples of `/api/v3/time`.
+/// Until the first sample succeeds the local clock is used as is.
+pub struct ServerClock {
+    samples: Mutex<VecDeque<ClockOffset>>,
+}
+
+impl ServerClock {
+    pub fn new() -> Self {
+        ServerClock {
+            samples: Mutex::new(VecDeque::with_capacity(MAX_SAMPLES)),
+        }
+    }
+
+    /// Clock shared by every order book of the process
+    pub fn global() -> &'static ServerClock {
+        GLOBAL.get_or_init(ServerClock::new)
+    }
+
+    pub fn record(&self, sample: ClockOffset) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == MAX_SAMPLES {
+            let _ = samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// The recent sample with the shortest round trip, `None` before the first sample
+    pub fn offset(&self) -> Option<ClockOffset> {
+        self.samples.lock().unwrap().iter().min_by_key(|sample| sample.uncertainty_ms).copied()
+    }
+
+    pub fn now_ms(&self) -> i64 {
+        local_ms() + self.offset().map_or(0, |offset| offset.offset_ms)
+    }
+
+    /// Take one sample of the exchange time
+    pub async fn sync(&self, client: &reqwest::Client) -> Result<ClockOffset, DepthError> {
+        WeightLimiter::global().acquire(TIME_WEIGHT).await;
+
+        let sent_at = local_ms();
+        let response = client.get(TIME_URL).send().await?;
+        let received_at = local_ms();
+        let status = response.status().as_u16();
+        let body = response.text().await?;
+        if !(200..300).contains(&status) {
+            return Err(status_error(status, None, &body))
+        }
+        let time: ServerTime = serde_json::from_str(&body).map_err(|e| DepthError::parse(&body, e))?;
+
+        let sample = ClockOffset::from_sample(sent_at, time.server_time, received_at);
+        self.record(sample);
+        Ok(sample)
+    }
+
+    /// Sample the exchange time every `interval`, forever
+    pub async fn run(&self, interval: Duration) {
+        let client = reqwest::Client::new();
+        let metrics = Metrics::global();
+        loop {
+            match self.sync(&client).await {
+                Ok(sample) => {
+                    let offset = self.offset().unwrap_or(sample);
+                    debug!(sample_offset_ms = sample.offset_ms, offset_ms = offset.offset_ms, uncertainty_ms = offset.uncertainty_ms, "clock synced");
+                    metrics.clock_offset_ms.set(offset.offset_ms);
+                    metrics.clock_uncertainty_ms.set(offset.uncertainty_ms);
+                },
+                Err(error) => warn!(%error, "fail to sync clock"),
+            }
+            sleep(interval).await;
+        }
+    }
+}
+
+impl Default for ServerClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn clock_offset_estimate(){
+    // Sent at 1000, answered at 1100, server read 1250 at our 1050
+    let sample = ClockOffset::from_sample(1000, 1250, 1100);
+    assert_eq!(sample, ClockOffset { offset_ms: 200, uncertainty_ms: 50 });
+
+    let clock = ServerClock::new();
+    assert!(clock.offset().is_none());
+    clock.record(sample);
+    clock.record(ClockOffset::from_sample(2000, 2190, 2020));
+    clock.record(ClockOffset::from_sample(3000, 3400, 3300));
+    assert_eq!(clock.offset(), Some(ClockOffset { offset_ms: 180, uncertainty_ms: 10 }));
+}
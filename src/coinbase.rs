@@ -0,0 +1,232 @@
+use serde::Deserialize;
+use serde_json::json;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use url::Url;
+use crate::deep::{BinanceSnapshot, DepthRow};
+use crate::error::DepthError;
+use crate::source::{malformed, rows, BookUpdate, OrderBookSource, SourceMessage};
+
+const STREAM: &str = "wss://advanced-trade-ws.coinbase.com";
+/// Level2 isn't sent on an interval, every change of the book goes out as it
+/// happens and busy products like BTC-USD send many messages a second
+const BUFFER_CAPACITY: usize = 1000;
+
+/// Public channel of Coinbase Advanced Trade
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoinbaseChannel {
+    /// Every level, snapshot then updates
+    Level2,
+    /// Best bid and ask with their quantities, sent on every trade
+    Ticker,
+}
+
+impl CoinbaseChannel {
+    fn name(self) -> &'static str {
+        match self {
+            CoinbaseChannel::Level2 => "level2",
+            CoinbaseChannel::Ticker => "ticker",
+        }
+    }
+
+    /// Channel of the messages, level2 data comes on "l2_data"
+    fn data_channel(self) -> &'static str {
+        match self {
+            CoinbaseChannel::Level2 => "l2_data",
+            CoinbaseChannel::Ticker => "ticker",
+        }
+    }
+}
+
+/// Book of a Coinbase Advanced Trade product (e.g. "BTC-USD").
+/// `sequence_num` of the connection links the messages, a skipped number is a gap.
+pub struct CoinbaseSource {
+    product_id: String,
+    channel: CoinbaseChannel,
+}
+
+impl CoinbaseSource {
+    /// Level2 book
+    pub fn new(product_id: &str) -> Self {
+        CoinbaseSource { product_id: product_id.to_uppercase(), channel: CoinbaseChannel::Level2 }
+    }
+
+    /// Top of the book from the ticker channel, each message is a snapshot
+    /// of one bid and one ask to compare level2 against
+    pub fn ticker(product_id: &str) -> Self {
+        CoinbaseSource { product_id: product_id.to_uppercase(), channel: CoinbaseChannel::Ticker }
+    }
+}
+
+#[derive(Deserialize)]
+struct CoinbaseMessage {
+    #[serde(rename = "type")]
+    ttype: Option<String>,
+    message: Option<String>,
+    channel: Option<String>,
+    timestamp: Option<String>,
+    sequence_num: Option<i64>,
+    #[serde(default)]
+    events: Vec<CoinbaseEvent>,
+}
+
+#[derive(Deserialize)]
+struct CoinbaseEvent {
+    /// "snapshot" or "update", missing on events of other channels
+    #[serde(rename = "type", default)]
+    ttype: String,
+    #[serde(default)]
+    updates: Vec<Level2Update>,
+    #[serde(default)]
+    tickers: Vec<Ticker>,
+}
+
+/// Prices and sizes are decimal strings, `new_quantity` "0" removes the level
+#[derive(Deserialize)]
+struct Level2Update {
+    side: String,
+    price_level: String,
+    new_quantity: String,
+}
+
+#[derive(Deserialize)]
+struct Ticker {
+    product_id: String,
+    best_bid: String,
+    best_bid_quantity: String,
+    best_ask: String,
+    best_ask_quantity: String,
+}
+
+/// RFC 3339 time, e.g. "2023-02-09T20:32:50.714964855Z", in ms
+fn time_ms(text: &str, time: &str) -> Result<i64, DepthError> {
+    let time = OffsetDateTime::parse(time, &Rfc3339).map_err(|_| malformed(text, "Bad timestamp"))?;
+    Ok((time.unix_timestamp_nanos() / 1_000_000) as i64)
+}
+
+impl OrderBookSource for CoinbaseSource {
+    fn stream(&self) -> String {
+        format!("coinbase/{}", self.channel.name())
+    }
+
+    fn stream_url(&self) -> Url {
+        Url::parse(STREAM).expect("Bad URL")
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        let subscribe = json!({
+            "type": "subscribe",
+            "product_ids": [self.product_id],
+            "channel": self.channel.name(),
+        });
+        vec![subscribe.to_string()]
+    }
+
+    fn parse(&self, text: &str) -> Result<Option<SourceMessage>, DepthError> {
+        let message: CoinbaseMessage = serde_json::from_str(text).map_err(|e| DepthError::parse(text, e))?;
+        if message.ttype.as_deref() == Some("error") {
+            return Err(DepthError::Venue(message.message.unwrap_or_else(|| text.to_string())))
+        }
+        // Subscriptions and heartbeats are numbered on their own channels
+        if message.channel.as_deref() != Some(self.channel.data_channel()) {
+            return Ok(None)
+        }
+
+        let sequence_num = message.sequence_num.ok_or_else(|| malformed(text, "Missing sequence_num"))?;
+        if self.channel == CoinbaseChannel::Ticker {
+            let ticker = message.events.into_iter()
+                .flat_map(|event| event.tickers)
+                .rfind(|ticker| ticker.product_id == self.product_id);
+            return match ticker {
+                Some(ticker) => Ok(Some(SourceMessage::Snapshot(BinanceSnapshot {
+                    last_update_id: sequence_num,
+                    bids: rows(text, [(ticker.best_bid, ticker.best_bid_quantity)])?,
+                    asks: rows(text, [(ticker.best_ask, ticker.best_ask_quantity)])?,
+                }))),
+                None => Ok(None),
+            }
+        }
+        let mut snapshot = false;
+        let mut bids = Vec::new();
+        let mut asks = Vec::new();
+        for event in message.events {
+            snapshot |= event.ttype == "snapshot";
+            for update in event.updates {
+                let row = match (update.price_level.parse(), update.new_quantity.parse()) {
+                    (Ok(price), Ok(amount)) => DepthRow { price, amount },
+                    _ => return Err(malformed(text, "Bad price_level or new_quantity")),
+                };
+                match update.side.as_str() {
+                    "bid" => bids.push(row),
+                    "offer" | "ask" => asks.push(row),
+                    _ => return Err(malformed(text, "Unknown side")),
+                }
+            }
+        }
+
+        if snapshot {
+            return Ok(Some(SourceMessage::Snapshot(BinanceSnapshot { last_update_id: sequence_num, bids, asks })))
+        }
+
+        let timestamp = message.timestamp.ok_or_else(|| malformed(text, "Missing timestamp"))?;
+        Ok(Some(SourceMessage::Update(BookUpdate {
+            event_time: time_ms(text, &timestamp)?,
+            first_update_id: sequence_num,
+            last_update_id: sequence_num,
+            previous_update_id: sequence_num - 1,
+            bids,
+            asks,
+            checksum: None,
+        })))
+    }
+
+    fn snapshot_on_stream(&self) -> bool {
+        true
+    }
+
+    fn buffer_capacity(&self) -> usize {
+        BUFFER_CAPACITY
+    }
+
+    fn match_snapshot(&self, update: &BookUpdate, last_update_id: i64) -> bool {
+        update.previous_update_id == last_update_id
+    }
+}
+
+#[test]
+fn coinbase_fixture_sequence(){
+    use crate::source::{replay, Sequence};
+
+    let source = CoinbaseSource::new("btc-usd");
+    let (book, updates) = replay(&source, include_str!("../fixtures/coinbase_level2.jsonl"), |_| ());
+
+    // 13 never came
+    let sequences: Vec<Sequence> = updates.iter().map(|(applied, _)| applied.sequence).collect();
+    assert_eq!(sequences, vec![Sequence::Apply, Sequence::Apply, Sequence::Gap]);
+    assert!(updates.iter().all(|(_, result)| result.is_ok()));
+    assert_eq!(updates[0].0.event_time % 1000, 12);
+    assert_eq!(updates[1].0.event_time % 1000, 212);
+    assert_eq!(book.id(), 12);
+    assert_eq!(
+        book.top_bids(2),
+        vec![DepthRow { price: 21921.5, amount: 0.1 }, DepthRow { price: 21921.3, amount: 0.02 }]
+    );
+    assert_eq!(book.top_asks(1), vec![DepthRow { price: 21921.74, amount: 0.75 }]);
+}
+
+#[test]
+fn coinbase_ticker_is_top_snapshot(){
+    let source = CoinbaseSource::ticker("btc-usd");
+    assert_eq!(source.subscribe_messages(), vec![r#"{"channel":"ticker","product_ids":["BTC-USD"],"type":"subscribe"}"#]);
+    let text = r#"{"channel":"ticker","client_id":"","timestamp":"2023-02-09T20:30:37.167359596Z","sequence_num":7,"events":[{"type":"update","tickers":[{"type":"ticker","product_id":"BTC-USD","price":"21932.98","volume_24_h":"16038.28770938","best_bid":"21931.98","best_bid_quantity":"0.5","best_ask":"21933.98","best_ask_quantity":"1.25"}]}]}"#;
+    match source.parse(text).unwrap() {
+        Some(SourceMessage::Snapshot(snapshot)) => {
+            assert_eq!(snapshot.last_update_id, 7);
+            assert_eq!(snapshot.bids, vec![DepthRow { price: 21931.98, amount: 0.5 }]);
+            assert_eq!(snapshot.asks, vec![DepthRow { price: 21933.98, amount: 1.25 }]);
+        },
+        other => panic!("Expect a snapshot, found {:?}", other),
+    }
+    // Level2 data is not for the ticker book
+    assert!(source.parse(include_str!("../fixtures/coinbase_level2.jsonl").lines().nth(1).unwrap()).unwrap().is_none());
+}
@@ -1,57 +1,276 @@
 use std::collections::VecDeque;
-use crate::deep::{LevelEvent, Event, BinanceSpotOrderBookSnapshot, Shared, BinanceSnapshot};
+use crate::deep::{BinanceSpotOrderBookSnapshot, Shared};
+use crate::error::DepthError;
+use crate::rest::fetch_contract_size;
+use crate::source::{apply_update, Applied, BookUpdate, OrderBookSource, Sequence, SourceMessage};
+use crate::binance::{parse_level_event, stream_url, BinanceSource, BOOK_TICKER_STREAM, COIN_FUTURES_EXCHANGE_INFO, LEVEL_DEPTH_STREAM};
+pub use crate::binance::Market;
+use crate::metrics::{Metrics, StreamMetrics};
+use crate::latency::{LatencyReport, StreamLatency};
+use crate::clock::now_ms;
+use crate::ticker::{BookTicker, TickerChecker, TopCheck, TopOfBookMismatch};
+use crate::trades::{Trade, TradeCorrelator, TradeStream};
+use crate::audit::{audit, AuditReport, LEVEL_AUDIT_DEPTH};
 use tokio_tungstenite::connect_async;
-use url::Url;
+use tokio_tungstenite::tungstenite::Message;
 use tokio::time::{sleep, Duration};
-use futures_util::StreamExt;
-use anyhow::{Result, Error};
-use anyhow::{bail, anyhow};
-use tokio::sync::Mutex;
+use futures_util::{SinkExt, StreamExt};
+use anyhow::Result;
+use tokio::sync::{broadcast, watch, Mutex, Notify};
+use tracing::{debug, info, info_span, trace, warn, Instrument};
 // use tokio::select;
 use std::sync::{Arc, RwLock};
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
-use futures_util::future::err;
+use std::time::Instant;
 // use tokio::spawn;
 
-const DEPTH_URL: &str = "wss://stream.binance.com:9443/ws/bnbbtc@depth@100ms";
-const LEVEL_DEPTH_URL: &str = "wss://stream.binance.com:9443/ws/bnbbtc@depth20@100ms";
-const REST: &str = "https://api.binance.com/api/v3/depth?symbol=BNBBTC&limit=1000";
-const MAX_BUFFER: usize = 30;
+pub const DEFAULT_SYMBOL: &str = "bnbbtc";
+const MAX_EVENTS: usize = 64;
+/// Diff events buffered before and after an audit snapshot is fetched
+const AUDIT_BUFFER_BEFORE: Duration = Duration::from_secs(1);
+const AUDIT_BUFFER_AFTER: Duration = Duration::from_secs(2);
+/// Time for the live books to apply the buffered events
+const AUDIT_SETTLE: Duration = Duration::from_secs(2);
+
+/// Lifecycle notifications of a `BinanceSpotOrderBook`
+#[derive(Debug, Clone)]
+pub enum BookEvent {
+    /// Book rebuilt from a snapshot, up to date with `last_update_id`
+    Synced { last_update_id: i64 },
+    /// Something failed, the book may be out of sync until the next `Synced`
+    Error(Arc<DepthError>),
+    /// Top of the book differs from bookTicker at the same update id
+    TopOfBookMismatch(TopOfBookMismatch),
+}
 
+/// Order book of a Binance market, spot unless built with `for_market`
 pub struct BinanceSpotOrderBook {
+    market: Market,
+    /// Lower case, as in stream names
+    symbol: String,
     status: Arc<Mutex<bool>>,
     shared: Arc<RwLock<Shared>>,
+    events: broadcast::Sender<BookEvent>,
+    depth_latency: Arc<std::sync::Mutex<StreamLatency>>,
+    level_latency: Arc<std::sync::Mutex<StreamLatency>>,
+    /// Set once a trade stream is ingested with `trades`
+    trades: Arc<std::sync::Mutex<Option<TradeCorrelator>>>,
+}
+
+/// Log `error` and send it to subscribers
+fn report(events: &broadcast::Sender<BookEvent>, error: DepthError) {
+    warn!(%error, "order book error");
+    let _ = events.send(BookEvent::Error(Arc::new(error)));
+}
+
+/// Sequence a buffered update against `book` and apply it if it follows,
+/// `first` for the one overlapping the snapshot. Text updates are read once,
+/// the trade correlator seeing their levels as they are applied.
+/// `None` for snapshots, which the sync loop loads
+fn apply_message<S: OrderBookSource>(
+    source: &S,
+    trades: &std::sync::Mutex<Option<TradeCorrelator>>,
+    book: &mut Shared,
+    message: SourceMessage,
+    first: bool,
+) -> Result<Option<Applied>, DepthError> {
+    let mut trades = trades.lock().unwrap();
+    match message {
+        SourceMessage::Update(update) => apply_update(source, book, update, first, trades.as_mut()).map(Some),
+        SourceMessage::Text(text) => source.apply_text(book, &text, first, trades.as_mut()),
+        SourceMessage::Snapshot(_) => Ok(None),
+    }
+}
+
+/// Record the time from exchange event to local receive of an update
+fn record_network(latency: &std::sync::Mutex<StreamLatency>, metrics: &StreamMetrics, applied: &Applied, received: Instant) {
+    let network = received_time(received) - applied.event_time;
+    metrics.message_lag_ms.set(network);
+    metrics.network_latency_ms.observe(network as f64);
+    latency.lock().unwrap().network.record(network);
+}
+
+/// Push the updates `stream` sends during `duration`
+async fn buffer_updates<W, S>(
+    stream: &mut W,
+    source: &S,
+    events: &broadcast::Sender<BookEvent>,
+    duration: Duration,
+    updates: &mut Vec<BookUpdate>,
+) where
+    W: futures_util::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    S: OrderBookSource,
+{
+    let deadline = tokio::time::Instant::now() + duration;
+    while let Ok(Some(Ok(msg))) = tokio::time::timeout_at(deadline, stream.next()).await {
+        if !msg.is_text() {
+            continue
+        }
+        let text = match msg.into_text() {
+            Ok(text) => text,
+            Err(_) => continue,
+        };
+        match source.parse(&text) {
+            Ok(Some(SourceMessage::Update(update))) => updates.push(update),
+            Ok(_) => (),
+            Err(e) => report(events, e),
+        }
+    }
+}
+
+/// Exchange corrected time of a message received at `received`
+fn received_time(received: Instant) -> i64 {
+    now_ms() - received.elapsed().as_millis() as i64
+}
+
+/// Record the time from local receive to applied into the book,
+/// measured on the monotonic clock so clock offset updates don't show up
+fn record_processing(latency: &std::sync::Mutex<StreamLatency>, metrics: &StreamMetrics, received: Instant) {
+    let processing = received.elapsed().as_millis() as i64;
+    metrics.processing_latency_ms.observe(processing as f64);
+    latency.lock().unwrap().processing.record(processing);
+}
+
+impl Default for BinanceSpotOrderBook {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl BinanceSpotOrderBook {
 
     pub fn new() -> Self {
+        Self::with_history(0)
+    }
+
+    /// Order book that also keeps its last `capacity` versions
+    /// for point-in-time queries
+    pub fn with_history(capacity: usize) -> Self {
+        Self::for_symbol(DEFAULT_SYMBOL, capacity)
+    }
+
+    /// Order book of `symbol` (e.g. "btcusdt") keeping its last
+    /// `history_capacity` versions, `0` disables history
+    pub fn for_symbol(symbol: &str, history_capacity: usize) -> Self {
+        Self::for_market(Market::Spot, symbol, history_capacity)
+    }
+
+    /// Order book of `symbol` on `market`, e.g. `Market::UsdFutures` and "btcusdt"
+    pub fn for_market(market: Market, symbol: &str, history_capacity: usize) -> Self {
         BinanceSpotOrderBook {
+            market,
+            symbol: symbol.to_lowercase(),
             status: Arc::new(Mutex::new(false)),
-            shared: Arc::new(RwLock::new(Shared::new()))
+            shared: Arc::new(RwLock::new(Shared::with_history(history_capacity))),
+            events: broadcast::channel(MAX_EVENTS).0,
+            depth_latency: Arc::new(std::sync::Mutex::new(StreamLatency::default())),
+            level_latency: Arc::new(std::sync::Mutex::new(StreamLatency::default())),
+            trades: Arc::new(std::sync::Mutex::new(None)),
+        }
+    }
+
+    pub fn market(&self) -> Market {
+        self.market
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    /// USD value of one contract on COIN-M, whose quantities are in contracts,
+    /// `None` on other markets where quantities are in base asset
+    pub async fn contract_size(&self) -> Result<Option<f64>, DepthError> {
+        match self.market {
+            Market::CoinFutures => fetch_contract_size(COIN_FUTURES_EXCHANGE_INFO, &self.symbol).await.map(Some),
+            _ => Ok(None),
         }
     }
 
+    /// Receive `BookEvent`s, errors included, from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<BookEvent> {
+        self.events.subscribe()
+    }
+
+    /// Latency percentiles of the diff depth stream
+    pub fn depth_latency(&self) -> LatencyReport {
+        self.depth_latency.lock().unwrap().report()
+    }
+
+    /// Latency percentiles of the depth20 stream,
+    /// which has no event time so only processing is measured
+    pub fn level_latency(&self) -> LatencyReport {
+        self.level_latency.lock().unwrap().report()
+    }
+
     /// acquire a order book with "depth method"
     pub fn depth(&self) -> Result<()> {
+        self.sync_source(BinanceSource::new(self.market, &self.symbol));
+        Ok(())
+    }
+
+    /// Keep the book in sync from the updates and snapshots of `source`
+    pub fn sync_source<S: OrderBookSource>(&self, source: S) {
+        let source = Arc::new(source);
+        let stream_label = source.stream();
         let shared = self.shared.clone();
         let status = self.status.clone();
-        let buffer = Arc::new(Mutex::new(VecDeque::<Event>::new()));
+        // Messages with their local receive instant
+        let buffer = Arc::new(Mutex::new(VecDeque::<(SourceMessage, Instant)>::new()));
+        let symbol = self.symbol.clone();
+        // Sources sending snapshots on the stream resync by reconnecting
+        let reconnect = Arc::new(Notify::new());
 
         // Thread to maintain buffer from stream
         let buffer_clone1 = buffer.clone();
+        let events = self.events.clone();
+        let source_clone1 = source.clone();
+        let metrics = Metrics::global().stream(&symbol, &stream_label);
+        let metrics_clone1 = metrics.clone();
+        let latency = self.depth_latency.clone();
+        let reconnect_clone1 = reconnect.clone();
+        let trades = self.trades.clone();
         tokio::spawn(async move {
-            println!("Start buffer maintain thread");
+            info!("Start buffer maintain thread");
+            let metrics = metrics_clone1;
+            let source = source_clone1;
             loop{
-                let url = Url::parse(DEPTH_URL).expect("Bad URL");
+                let url = source.stream_url();
 
                 let res = connect_async(url).await;
                 let mut stream = match res{
                     Ok((stream, _)) => stream,
-                    Err(e) => return anyhow!("{:?}", e),
+                    Err(e) => {
+                        report(&events, e.into());
+                        metrics.reconnects.inc();
+                        sleep(Duration::from_millis(1000)).await;
+                        continue
+                    },
                 };
 
-                while let Ok(msg) = stream.next().await.unwrap(){ //
+                for subscribe in source.subscribe_messages() {
+                    if let Err(e) = stream.send(Message::Text(subscribe)).await {
+                        report(&events, e.into());
+                    }
+                }
+
+                loop {
+                    let msg = tokio::select! {
+                        msg = stream.next() => match msg {
+                            Some(msg) => msg,
+                            None => break,
+                        },
+                        _ = reconnect_clone1.notified() => {
+                            info!("reconnect for a new snapshot");
+                            break
+                        },
+                    };
+                    let msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            report(&events, e.into());
+                            break
+                        },
+                    };
+
                     if !msg.is_text() {
                         continue
                     }
@@ -61,45 +280,68 @@ impl BinanceSpotOrderBook {
                         Err(_) => continue,
                     };
 
-                    let event: Event = match serde_json::from_str(&text){
-                        Ok(e) => e,
-                        Err(_) => continue,
+                    let message = match source.parse_owned(text){
+                        Ok(Some(message)) => message,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            report(&events, e);
+                            continue
+                        },
                     };
+                    let received = Instant::now();
 
                     let mut guard = buffer_clone1.lock().await;
 
-                    if (*guard).len() == MAX_BUFFER {
+                    if let SourceMessage::Snapshot(_) = message {
+                        // Older messages are superseded
+                        (*guard).clear();
+                    }
+                    if (*guard).len() == source.buffer_capacity() {
                         let _ = (*guard).pop_front();
-                        (*guard).push_back(event);
+                        (*guard).push_back((message, received));
                     } else {
-                        (*guard).push_back(event);
+                        (*guard).push_back((message, received));
                     }
                 };
+                metrics.reconnects.inc();
             }
-        });
+        }.instrument(info_span!("depth", symbol = %symbol, stream = %stream_label)));
 
         // Thread to maintain Order Book
         let buffer_clone2 = buffer.clone();
+        let events = self.events.clone();
         tokio::spawn(async move{
             let mut default_exit = 0;
-            println!("Start OrderBook thread");
+            info!("Start OrderBook thread");
             loop {
-                let res : Result<()> = {
+                let res : Result<(), DepthError> = async {
                     {
                         let mut guard = status.lock().await;
                         (*guard) = false;
+                        metrics.sync_state.set(0);
                     }
                     // println!("Dropped the status.lock");
                     // Wait for a while to collect event into buffer
                     sleep(Duration::from_millis(1000)).await;
                     // println!("Calling Https://");
-                    let snapshot: BinanceSnapshot = reqwest::get(REST)
-                        .await?
-                        .json()
-                        .await?;
+                    let mut stream_snapshot_received = None;
+                    let snapshot = if source.snapshot_on_stream() {
+                        // Start from the last snapshot sent on the stream
+                        let mut guard = buffer_clone2.lock().await;
+                        let position = (*guard).iter().rposition(|(message, _)| matches!(message, SourceMessage::Snapshot(_)));
+                        match position.and_then(|position| (*guard).drain(..=position).next_back()) {
+                            Some((SourceMessage::Snapshot(snapshot), received)) => {
+                                stream_snapshot_received = Some(received);
+                                snapshot
+                            },
+                            _ => return Err(DepthError::MissingSnapshot),
+                        }
+                    } else {
+                        source.fetch_snapshot().await?
+                    };
                     // println!("Done Calling Https://");
                     sleep(Duration::from_millis(500)).await;
-                    let mut buffer = VecDeque::<Event>::new();
+                    let mut buffer = VecDeque::<(SourceMessage, Instant)>::new();
                     // println!("Acquiring buffer_clone2 lock");
                     {
                         let mut guard = buffer_clone2.lock().await;
@@ -107,42 +349,73 @@ impl BinanceSpotOrderBook {
                     }
                     // println!("Dropped buffer_clone2 lock");
 
-                    println!("Buffer len {}", buffer.len());
-                    println!("Snap shot {}", snapshot.last_update_id); // 2861806778
+                    debug!(
+                        buffer_len = buffer.len(),
+                        snapshot_id = snapshot.last_update_id, // 2861806778
+                        "buffered events"
+                    );
                     let mut overbook_setup = false;
-                    while let Some(event) = buffer.pop_front() {
-                        println!(" Event {}-{}", event.first_update_id, event.last_update_id);
-                        // Event 2861806779-2861806780
-
-                        if snapshot.last_update_id >= event.last_update_id  {
-                            // step 4
-                            continue
-                        }
-
-                        if event.match_snapshot(snapshot.last_update_id) {
-                            println!(" Found match snapshot ");
-                            let mut orderbook = shared.write().unwrap();
-                            orderbook.load_snapshot(&snapshot);
-                            orderbook.add_event(event);
-
-                            overbook_setup = true;
-
-                            break;
-                        } else {
-                            // println!(" No match ");
-                        }
+                    if let Some(received) = stream_snapshot_received {
+                        // The snapshot is already in line with the stream,
+                        // it comes on a new connection so older versions may be stale
+                        let mut orderbook = shared.write().unwrap();
+                        orderbook.clear_history();
+                        orderbook.apply_snapshot(&snapshot, received_time(received));
+                        metrics.events_applied.inc();
+                        metrics.observe_book(&orderbook);
+                        record_processing(&latency, &metrics, received);
+                        overbook_setup = true;
+                    } else {
+                        // Updates are sequenced and applied against the snapshot as they are read
+                        let mut orderbook = shared.write().unwrap();
+                        orderbook.load_snapshot(&snapshot);
+                        while let Some((message, received)) = buffer.pop_front() {
+                            let applied = match apply_message(&*source, &trades, &mut orderbook, message, true) {
+                                Ok(Some(applied)) => applied,
+                                Ok(None) => continue,
+                                Err(e) => {
+                                    if let DepthError::ChecksumMismatch { .. } = e {
+                                        metrics.checksum_mismatches.inc();
+                                    }
+                                    return Err(e)
+                                },
+                            };
+                            let (first_update_id, last_update_id) = (applied.first_update_id, applied.last_update_id);
+                            // Event 2861806779-2861806780
+                            trace!(first_update_id, last_update_id, "check event");
+                            record_network(&latency, &metrics, &applied, received);
+
+                            match applied.sequence {
+                                Sequence::Apply => {
+                                    debug!(first_update_id, last_update_id, "found match snapshot");
+                                    metrics.events_applied.inc();
+                                    metrics.observe_book(&orderbook);
+                                    record_processing(&latency, &metrics, received);
+
+                                    overbook_setup = true;
 
-                        if event.first_update_id > snapshot.last_update_id + 1 {
-                            println!("Rest event is not usable, need a new snap shot ");
-                            println!();
-                            break;
+                                    break;
+                                },
+                                // Not matching yet newer than the snapshot
+                                Sequence::Gap => {
+                                    return Err(DepthError::StaleSnapshot {
+                                        last_update_id: snapshot.last_update_id,
+                                        first_update_id,
+                                    })
+                                },
+                                // step 4, older than the snapshot
+                                Sequence::Skip => (),
+                            }
                         }
-
                     }
 
                     if overbook_setup {
                         let mut guard = status.lock().await;
                         (*guard) = true;
+                        metrics.sync_state.set(1);
+                        let last_update_id = shared.read().unwrap().id();
+                        info!(last_update_id, sync_state = "synced", "order book synced");
+                        let _ = events.send(BookEvent::Synced { last_update_id });
                     }
 
                     if overbook_setup {
@@ -159,32 +432,56 @@ impl BinanceSpotOrderBook {
 
                             // let instance = Instant::now();
                             // let buffer_len = buffer.len();
-                            let mut need_new_snap_snot = false;
+                            let mut need_new_snap_snot = None;
 
                             // Acquire guard <orderbook>
                             let mut orderbook = shared.write().unwrap();
 
 
-                            while let Some(event) = buffer.pop_front() {
-
-                                if event.first_update_id > orderbook.id() + 1 {
-                                    println!("All event is not usable, need a new snap shot ");
-                                    println!("order book {}, Event {}-{}",
-                                             orderbook.id(), event.first_update_id, event.last_update_id);
-                                    need_new_snap_snot = true;
-                                    break;
-                                } else if event.first_update_id == orderbook.id() + 1 {
-                                    // println!("Update complete");
-                                    orderbook.add_event(event)
-                                } else {
+                            while let Some((message, received)) = buffer.pop_front() {
+                                // Book sent again on the stream
+                                if let SourceMessage::Snapshot(snapshot) = &message {
+                                    orderbook.apply_snapshot(snapshot, received_time(received));
+                                    metrics.events_applied.inc();
+                                    record_processing(&latency, &metrics, received);
                                     continue
                                 }
 
+                                let applied = match apply_message(&*source, &trades, &mut orderbook, message, false) {
+                                    Ok(Some(applied)) => applied,
+                                    Ok(None) => continue,
+                                    Err(e) => {
+                                        if let DepthError::ChecksumMismatch { .. } = e {
+                                            metrics.checksum_mismatches.inc();
+                                        }
+                                        need_new_snap_snot = Some(e);
+                                        break;
+                                    },
+                                };
+                                record_network(&latency, &metrics, &applied, received);
+                                match applied.sequence {
+                                    Sequence::Gap => {
+                                        metrics.gaps.inc();
+                                        need_new_snap_snot = Some(DepthError::SequenceGap {
+                                            expected: orderbook.id() + 1,
+                                            found: applied.previous_update_id + 1,
+                                        });
+                                        break;
+                                    },
+                                    Sequence::Apply => {
+                                        // println!("Update complete");
+                                        metrics.events_applied.inc();
+                                        record_processing(&latency, &metrics, received);
+                                    },
+                                    Sequence::Skip => continue,
+                                }
+
                             }
+                            metrics.observe_book(&orderbook);
 
-                            if need_new_snap_snot {
+                            if let Some(error) = need_new_snap_snot {
 
-                                break;
+                                return Err(error);
                             }
 
                             // println!("deal {} Event used {}ms", buffer_len, instance.elapsed().as_millis());
@@ -193,24 +490,26 @@ impl BinanceSpotOrderBook {
                     }
 
                     Ok(())
-                };
+                }.await;
 
-                match res {
-                    Ok(_) => (),
-                    Err(e) => println!("Error happen when running code: {:?}", e),
+                if let Err(e) = res {
+                    report(&events, e);
+                }
+                if source.snapshot_on_stream() {
+                    // A new connection comes with a new snapshot
+                    reconnect.notify_one();
                 }
+                info!(sync_state = "resyncing", "need a new snap shot");
 
                 if default_exit > 20 {
-                    println!("Using default break");
+                    warn!(sync_state = "stopped", "Using default break");
                     break
                 }
 
                 default_exit += 1;
+                metrics.resyncs.inc();
             }
-            Ok::<(), Error>(())
-        });
-
-        Ok(())
+        }.instrument(info_span!("order_book", symbol = %self.symbol, stream = %stream_label)));
     }
 
     pub fn level_depth(&self) {
@@ -218,18 +517,24 @@ impl BinanceSpotOrderBook {
 
         // This is not actually used
         let status = self.status.clone();
+        let events = self.events.clone();
+        let market = self.market;
+        let url = stream_url(market, &self.symbol, LEVEL_DEPTH_STREAM);
+        let metrics = Metrics::global().stream(&self.symbol, &market.stream_label(LEVEL_DEPTH_STREAM));
+        let latency = self.level_latency.clone();
 
-
-        let _ = tokio::spawn(async move {
-            println!("Start Level Buffer maintain thread");
+        tokio::spawn(async move {
+            info!("Start Level Buffer maintain thread");
             loop{
-                let url = Url::parse(LEVEL_DEPTH_URL).expect("Bad URL");
+                let url = url.clone();
 
                 let res = connect_async(url).await;
                 let mut stream = match res{
                     Ok((stream, _)) => stream,
                     Err(e) => {
-                        println!("Error {:?}, reconnecting {}", e, LEVEL_DEPTH_URL);
+                        report(&events, e.into());
+                        metrics.reconnects.inc();
+                        sleep(Duration::from_millis(1000)).await;
                         continue
                     },
                 };
@@ -237,11 +542,18 @@ impl BinanceSpotOrderBook {
                 {
                     let mut guard = status.lock().await;
                     (*guard) = true;
+                    metrics.sync_state.set(1);
                 }
 
-                use std::time::{UNIX_EPOCH, SystemTime};
+                while let Some(msg) = stream.next().await {
+                    let msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            report(&events, e.into());
+                            break
+                        },
+                    };
 
-                while let Ok(msg) = stream.next().await.unwrap(){ //
                     if !msg.is_text() {
                         continue
                     }
@@ -251,31 +563,258 @@ impl BinanceSpotOrderBook {
                         Err(_) => continue,
                     };
 
-                    let level_event: LevelEvent = match serde_json::from_str(&text){
+                    let received = Instant::now();
+                    let received_at = now_ms();
+                    let level_event = match parse_level_event(market, &text){
                         Ok(e) => e,
-                        Err(_) => continue,
+                        Err(e) => {
+                            report(&events, DepthError::parse(&text, e));
+                            continue
+                        },
                     };
 
-                    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+                    trace!(last_update_id = level_event.last_update_id, "level event");
                     if let Ok(mut guard) = shared.write(){
-                        (*guard).set_level_event(level_event, time.as_millis() as i64);
+                        (*guard).set_level_event(level_event, received_at);
+                        metrics.events_applied.inc();
+                        metrics.observe_book(&guard);
+                        record_processing(&latency, &metrics, received);
+                    }
+                };
+                metrics.reconnects.inc();
+            }
+
+        }.instrument(info_span!("level_depth", %market, symbol = %self.symbol, stream = LEVEL_DEPTH_STREAM)));
+    }
+
+    /// Check the top of the book against `<symbol>@bookTicker` whenever
+    /// the book reaches the update id of a ticker. Mismatches are logged, counted
+    /// and sent as `BookEvent::TopOfBookMismatch`. With history enabled, tickers
+    /// matching an older version of the book are checked too.
+    pub fn book_ticker(&self) {
+        let shared = self.shared.clone();
+        let status = self.status.clone();
+        let events = self.events.clone();
+        let market = self.market;
+        let url = stream_url(market, &self.symbol, BOOK_TICKER_STREAM);
+        let metrics = Metrics::global().stream(&self.symbol, &market.stream_label(BOOK_TICKER_STREAM));
+
+        tokio::spawn(async move {
+            info!("Start book ticker thread");
+            let mut checker = TickerChecker::default();
+            loop{
+                let url = url.clone();
+
+                let res = connect_async(url).await;
+                let mut stream = match res{
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        report(&events, e.into());
+                        metrics.reconnects.inc();
+                        sleep(Duration::from_millis(1000)).await;
+                        continue
+                    },
+                };
+
+                while let Some(msg) = stream.next().await {
+                    let msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            report(&events, e.into());
+                            break
+                        },
+                    };
+
+                    if !msg.is_text() {
+                        continue
+                    }
+
+                    let text = match msg.into_text(){
+                        Ok(e) => e,
+                        Err(_) => continue,
+                    };
+
+                    let ticker: BookTicker = match serde_json::from_str(&text){
+                        Ok(e) => e,
+                        Err(e) => {
+                            report(&events, DepthError::parse(&text, e));
+                            continue
+                        },
+                    };
+                    trace!(update_id = ticker.update_id, "book ticker");
+                    checker.push(ticker);
+
+                    // Pending tickers would be checked against the history of the resynced book
+                    if !*status.lock().await {
+                        checker.clear();
+                        continue
+                    }
+                    let checks = checker.check(&shared.read().unwrap());
+                    for (_, check) in checks {
+                        match check {
+                            TopCheck::Match => metrics.top_matches.inc(),
+                            TopCheck::Unverified => metrics.top_unverified.inc(),
+                            TopCheck::Mismatch(mismatch) => {
+                                metrics.top_mismatches.inc();
+                                warn!(
+                                    update_id = mismatch.update_id,
+                                    ticker_bid = ?mismatch.ticker_bid,
+                                    book_bid = ?mismatch.book_bid,
+                                    ticker_ask = ?mismatch.ticker_ask,
+                                    book_ask = ?mismatch.book_ask,
+                                    "top of book differs from book ticker"
+                                );
+                                let _ = events.send(BookEvent::TopOfBookMismatch(mismatch));
+                            },
+                        }
                     }
                 };
+                metrics.reconnects.inc();
             }
 
-        });
+        }.instrument(info_span!("book_ticker", %market, symbol = %self.symbol, stream = BOOK_TICKER_STREAM)));
+    }
+
+    /// Ingest `<symbol>@trade` or `@aggTrade` and attribute the quantity
+    /// each depth update removes from a level to trades or cancellations,
+    /// see `with_trades` for the statistics
+    pub fn trades(&self, trade_stream: TradeStream) {
+        self.trades.lock().unwrap().get_or_insert_with(TradeCorrelator::new);
+        let trades = self.trades.clone();
+        let events = self.events.clone();
+        let market = self.market;
+        let url = stream_url(market, &self.symbol, trade_stream.name());
+        let metrics = Metrics::global().stream(&self.symbol, &market.stream_label(trade_stream.name()));
+
+        tokio::spawn(async move {
+            info!("Start trade thread");
+            loop{
+                let url = url.clone();
+
+                let res = connect_async(url).await;
+                let mut stream = match res{
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        report(&events, e.into());
+                        metrics.reconnects.inc();
+                        sleep(Duration::from_millis(1000)).await;
+                        continue
+                    },
+                };
+
+                while let Some(msg) = stream.next().await {
+                    let msg = match msg {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            report(&events, e.into());
+                            break
+                        },
+                    };
+
+                    if !msg.is_text() {
+                        continue
+                    }
+
+                    let text = match msg.into_text(){
+                        Ok(e) => e,
+                        Err(_) => continue,
+                    };
+
+                    let trade: Trade = match serde_json::from_str(&text){
+                        Ok(e) => e,
+                        Err(e) => {
+                            report(&events, DepthError::parse(&text, e));
+                            continue
+                        },
+                    };
+                    trace!(trade_id = trade.trade_id, "trade");
+                    if let Some(correlator) = trades.lock().unwrap().as_mut() {
+                        correlator.on_trade(trade);
+                    }
+                    metrics.events_applied.inc();
+                };
+                metrics.reconnects.inc();
+            }
+
+        }.instrument(info_span!("trades", %market, symbol = %self.symbol, stream = trade_stream.name())));
+    }
+
+    /// Read the trade statistics, `None` until `trades` is started
+    pub fn with_trades<R>(&self, f: impl FnOnce(&TradeCorrelator) -> R) -> Option<R> {
+        self.trades.lock().unwrap().as_ref().map(f)
+    }
+
+    /// Every `interval`, fetch a REST snapshot and diff this book and the
+    /// depth20 `level_book` against it, at an update id both have a version for.
+    /// The snapshot is rolled forward with diff events buffered on a separate
+    /// connection, so both books need their history enabled.
+    pub fn audit(&self, level_book: &BinanceSpotOrderBook, interval: Duration) -> watch::Receiver<Option<AuditReport>> {
+        let (sender, receiver) = watch::channel(None);
+        let source = BinanceSource::new(self.market, &self.symbol);
+        let depth = self.shared.clone();
+        let level = level_book.shared.clone();
+        let events = self.events.clone();
+        let metrics = Metrics::global();
+        let depth_differing = metrics.audit_differing_levels.with_label_values(&[&self.symbol, "depth"]);
+        let level_differing = metrics.audit_differing_levels.with_label_values(&[&self.symbol, "depth20"]);
+
+        tokio::spawn(async move {
+            info!("Start audit thread");
+            loop {
+                sleep(interval).await;
+
+                let mut stream = match connect_async(source.stream_url()).await {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        report(&events, e.into());
+                        continue
+                    },
+                };
+                let mut updates = Vec::new();
+                buffer_updates(&mut stream, &source, &events, AUDIT_BUFFER_BEFORE, &mut updates).await;
+                let snapshot = match source.fetch_snapshot().await {
+                    Ok(snapshot) => snapshot,
+                    Err(e) => {
+                        report(&events, e);
+                        continue
+                    },
+                };
+                buffer_updates(&mut stream, &source, &events, AUDIT_BUFFER_AFTER, &mut updates).await;
+                drop(stream);
+                sleep(AUDIT_SETTLE).await;
+
+                let depth_diff = audit(&source, &snapshot, &updates, usize::MAX, |id| {
+                    depth.read().unwrap().history().at_update_id(id).cloned()
+                });
+                let level_diff = audit(&source, &snapshot, &updates, LEVEL_AUDIT_DEPTH, |id| {
+                    level.read().unwrap().history().at_update_id(id).cloned()
+                });
+                for (book, diff, gauge) in [("depth", &depth_diff, &depth_differing), ("depth20", &level_diff, &level_differing)] {
+                    match diff {
+                        Some(diff) if diff.is_empty() => {
+                            gauge.set(0);
+                            debug!(book, update_id = diff.update_id, "book matches REST snapshot");
+                        },
+                        Some(diff) => {
+                            gauge.set(diff.len() as i64);
+                            warn!(book, update_id = diff.update_id, bids = ?diff.bids, asks = ?diff.asks, "book differs from REST snapshot");
+                        },
+                        None => warn!(book, snapshot_id = snapshot.last_update_id, "no book version to audit"),
+                    }
+                }
+                let _ = sender.send(Some(AuditReport { snapshot_id: snapshot.last_update_id, depth: depth_diff, level: level_diff }));
+            }
+        }.instrument(info_span!("audit", market = %self.market, symbol = %self.symbol)));
+        receiver
     }
 
     /// Get the snapshot of the current Order Book
     pub async fn get_snapshot(&self) -> Option<BinanceSpotOrderBookSnapshot>{
-        let mut current_status = false;
-
-        {
+        let current_status = {
             let status = self.status.clone();
             let status_guard = status.lock().await;
-            current_status = (*status_guard).clone();
-
-        }// Release the guard immediately
+            *status_guard
+        };// Release the guard immediately
 
 
         if current_status{
@@ -285,8 +824,16 @@ impl BinanceSpotOrderBook {
         }
 
     }
-}
-
-
 
+    /// Get the order book as it was once every update up to `update_id` was applied,
+    /// `None` if the id is older than the retained history
+    pub fn snapshot_at_update_id(&self, update_id: i64) -> Option<BinanceSpotOrderBookSnapshot> {
+        self.shared.read().unwrap().history().at_update_id(update_id).cloned()
+    }
 
+    /// Get the order book as it was at `time_stamp` (ms),
+    /// `None` if the time is older than the retained history
+    pub fn snapshot_at_time(&self, time_stamp: i64) -> Option<BinanceSpotOrderBookSnapshot> {
+        self.shared.read().unwrap().history().at_time(time_stamp).cloned()
+    }
+}
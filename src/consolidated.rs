@@ -0,0 +1,294 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use ordered_float::OrderedFloat;
+use crate::deep::{BinanceSpotOrderBookSnapshot, DepthRow, Shared};
+use crate::error::DepthError;
+
+/// Quote currencies, longest first, to split symbols without a separator
+const QUOTES: [&str; 10] = ["FDUSD", "USDT", "USDC", "BUSD", "TUSD", "USD", "EUR", "BTC", "ETH", "BNB"];
+/// Quotes pegged to the US dollar, consolidated as "USD"
+const USD_QUOTES: [&str; 6] = ["USD", "USDT", "USDC", "BUSD", "FDUSD", "TUSD"];
+
+/// Venue independent name of a spot instrument
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Instrument {
+    pub base: String,
+    /// USD for every dollar stablecoin
+    pub quote: String,
+}
+
+impl Instrument {
+    pub fn new(base: &str, quote: &str) -> Self {
+        Instrument { base: normalize_asset(base), quote: normalize_quote(quote) }
+    }
+
+    /// Read a venue symbol such as "btcusdt" (Binance, Bybit), "BTC-USDT" (OKX, Coinbase)
+    /// or "XBT/USD" (Kraken)
+    pub fn parse(symbol: &str) -> Result<Self, DepthError> {
+        let upper = symbol.to_uppercase();
+        if let Some((base, quote)) = upper.split_once(['-', '/', '_']) {
+            if !base.is_empty() && !quote.is_empty() {
+                return Ok(Instrument::new(base, quote))
+            }
+        }
+        QUOTES.iter()
+            .find_map(|quote| upper.strip_suffix(quote).filter(|base| !base.is_empty()).map(|base| Instrument::new(base, quote)))
+            .ok_or_else(|| DepthError::UnknownSymbol(symbol.to_string()))
+    }
+}
+
+impl fmt::Display for Instrument {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.base, self.quote)
+    }
+}
+
+/// Kraken names bitcoin XBT
+fn normalize_asset(asset: &str) -> String {
+    match asset.to_uppercase().as_str() {
+        "XBT" => "BTC".to_string(),
+        asset => asset.to_string(),
+    }
+}
+
+fn normalize_quote(quote: &str) -> String {
+    let quote = normalize_asset(quote);
+    if USD_QUOTES.contains(&quote.as_str()) {
+        "USD".to_string()
+    } else {
+        quote
+    }
+}
+
+/// Amount one venue shows at a consolidated level
+#[derive(Debug, Clone, PartialEq)]
+pub struct VenueAmount {
+    pub venue: String,
+    pub amount: f64,
+}
+
+/// Level of the consolidated ladder, `amount` sums the venues at that price
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsolidatedLevel {
+    pub price: f64,
+    pub amount: f64,
+    pub venues: Vec<VenueAmount>,
+}
+
+/// Best price of one venue
+#[derive(Debug, Clone, PartialEq)]
+pub struct VenueQuote {
+    pub venue: String,
+    pub price: f64,
+    pub amount: f64,
+}
+
+/// Best bid and offer across venues
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bbo {
+    pub bid: Option<VenueQuote>,
+    pub ask: Option<VenueQuote>,
+}
+
+/// A venue bids above the ask of another one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Crossing {
+    /// Where to sell
+    pub bid: VenueQuote,
+    /// Where to buy
+    pub ask: VenueQuote,
+    /// Amount tradable on both tops
+    pub amount: f64,
+    /// `bid - ask` over the mid price, in basis points
+    pub edge_bps: f64,
+}
+
+/// Book of one venue with prices in the consolidated quote
+struct VenueBook {
+    venue: String,
+    bids: Vec<DepthRow>,
+    asks: Vec<DepthRow>,
+}
+
+/// One ladder for an instrument made of the books of several venues,
+/// each level attributed to the venues quoting it.
+/// Prices of a venue are multiplied by its quote rate, e.g. the USD value of
+/// USDT, to bring every venue to the same quote.
+pub struct ConsolidatedBook {
+    instrument: Instrument,
+    quote_rates: BTreeMap<String, f64>,
+    books: Vec<VenueBook>,
+}
+
+impl ConsolidatedBook {
+    pub fn new(instrument: Instrument) -> Self {
+        ConsolidatedBook { instrument, quote_rates: BTreeMap::new(), books: Vec::new() }
+    }
+
+    pub fn instrument(&self) -> &Instrument {
+        &self.instrument
+    }
+
+    /// Multiply the prices of `venue` by `rate`, 1 by default
+    pub fn set_quote_rate(&mut self, venue: &str, rate: f64) {
+        self.quote_rates.insert(venue.to_string(), rate);
+    }
+
+    /// Set the book of `venue`, `symbol` as named by the venue
+    pub fn update(&mut self, venue: &str, symbol: &str, snapshot: &BinanceSpotOrderBookSnapshot) -> Result<(), DepthError> {
+        let instrument = Instrument::parse(symbol)?;
+        if instrument != self.instrument {
+            return Err(DepthError::InstrumentMismatch {
+                expected: self.instrument.to_string(),
+                found: instrument.to_string(),
+            })
+        }
+
+        let rate = self.quote_rates.get(venue).copied().unwrap_or(1.0);
+        let convert = |rows: &Vec<DepthRow>| {
+            rows.iter().map(|row| DepthRow { price: row.price * rate, amount: row.amount }).collect()
+        };
+        let book = VenueBook { venue: venue.to_string(), bids: convert(&snapshot.bids), asks: convert(&snapshot.asks) };
+        match self.books.iter_mut().find(|book| book.venue == venue) {
+            Some(existing) => *existing = book,
+            None => self.books.push(book),
+        }
+        Ok(())
+    }
+
+    /// `update` from a live book
+    pub fn update_book(&mut self, venue: &str, symbol: &str, book: &Shared) -> Result<(), DepthError> {
+        self.update(venue, symbol, &book.get_snapshot())
+    }
+
+    pub fn remove(&mut self, venue: &str) {
+        self.books.retain(|book| book.venue != venue);
+    }
+
+    pub fn venues(&self) -> Vec<&str> {
+        self.books.iter().map(|book| book.venue.as_str()).collect()
+    }
+
+    /// Best `n` consolidated bids, highest price first
+    pub fn top_bids(&self, n: usize) -> Vec<ConsolidatedLevel> {
+        let mut levels = merge(self.books.iter().map(|book| (&book.venue, &book.bids)));
+        levels.reverse();
+        levels.truncate(n);
+        levels
+    }
+
+    /// Best `n` consolidated asks, lowest price first
+    pub fn top_asks(&self, n: usize) -> Vec<ConsolidatedLevel> {
+        let mut levels = merge(self.books.iter().map(|book| (&book.venue, &book.asks)));
+        levels.truncate(n);
+        levels
+    }
+
+    /// Best bid and offer across venues, the larger amount wins ties
+    pub fn bbo(&self) -> Bbo {
+        let better = |a: &VenueQuote, b: &VenueQuote, higher: bool| {
+            let price = if higher { a.price > b.price } else { a.price < b.price };
+            price || (a.price == b.price && a.amount > b.amount)
+        };
+        let mut bbo = Bbo { bid: None, ask: None };
+        for (bid, ask) in self.books.iter().map(tops) {
+            if let Some(bid) = bid {
+                if bbo.bid.as_ref().is_none_or(|best| better(&bid, best, true)) {
+                    bbo.bid = Some(bid);
+                }
+            }
+            if let Some(ask) = ask {
+                if bbo.ask.as_ref().is_none_or(|best| better(&ask, best, false)) {
+                    bbo.ask = Some(ask);
+                }
+            }
+        }
+        bbo
+    }
+
+    /// Every pair of venues where one bids above the ask of the other,
+    /// widest edge first
+    pub fn crossings(&self) -> Vec<Crossing> {
+        let tops: Vec<_> = self.books.iter().map(tops).collect();
+        let mut crossings = Vec::new();
+        for (bid, _) in &tops {
+            for (_, ask) in &tops {
+                if let (Some(bid), Some(ask)) = (bid, ask) {
+                    if bid.venue != ask.venue && bid.price > ask.price {
+                        let mid = (bid.price + ask.price) / 2.0;
+                        crossings.push(Crossing {
+                            bid: bid.clone(),
+                            ask: ask.clone(),
+                            amount: bid.amount.min(ask.amount),
+                            edge_bps: (bid.price - ask.price) / mid * 10_000.0,
+                        });
+                    }
+                }
+            }
+        }
+        crossings.sort_by(|a, b| b.edge_bps.total_cmp(&a.edge_bps));
+        crossings
+    }
+}
+
+fn tops(book: &VenueBook) -> (Option<VenueQuote>, Option<VenueQuote>) {
+    let quote = |row: &DepthRow| VenueQuote { venue: book.venue.clone(), price: row.price, amount: row.amount };
+    (book.bids.first().map(quote), book.asks.first().map(quote))
+}
+
+/// Levels of every venue summed by price, lowest price first
+fn merge<'a>(sides: impl Iterator<Item = (&'a String, &'a Vec<DepthRow>)>) -> Vec<ConsolidatedLevel> {
+    let mut ladder: BTreeMap<OrderedFloat<f64>, Vec<VenueAmount>> = BTreeMap::new();
+    for (venue, rows) in sides {
+        for row in rows {
+            ladder.entry(OrderedFloat(row.price))
+                .or_default()
+                .push(VenueAmount { venue: venue.clone(), amount: row.amount });
+        }
+    }
+    ladder.into_iter()
+        .map(|(price, venues)| ConsolidatedLevel {
+            price: price.into_inner(),
+            amount: venues.iter().map(|venue| venue.amount).sum(),
+            venues,
+        })
+        .collect()
+}
+
+#[test]
+fn instrument_symbols(){
+    let btc_usd = Instrument::new("BTC", "USD");
+    assert_eq!(Instrument::parse("btcusdt").unwrap(), btc_usd);
+    assert_eq!(Instrument::parse("BTC-USDT").unwrap(), btc_usd);
+    assert_eq!(Instrument::parse("XBT/USD").unwrap(), btc_usd);
+    assert_eq!(Instrument::parse("BTCFDUSD").unwrap(), btc_usd);
+    assert_eq!(Instrument::parse("bnbbtc").unwrap(), Instrument::new("BNB", "BTC"));
+    assert!(matches!(Instrument::parse("usdt"), Err(DepthError::UnknownSymbol(_))));
+}
+
+#[test]
+fn consolidated_ladder_and_crossings(){
+    let snapshot = BinanceSpotOrderBookSnapshot::from_levels;
+    let mut book = ConsolidatedBook::new(Instrument::new("BTC", "USD"));
+    book.set_quote_rate("okx", 0.5);
+    book.update("binance", "btcusdt", &snapshot(&[(100.0, 1.0), (99.0, 2.0)], &[(101.0, 1.0)])).unwrap();
+    book.update("okx", "BTC-USDT", &snapshot(&[(200.0, 3.0)], &[(203.0, 1.0)])).unwrap();
+    book.update("kraken", "XBT/USD", &snapshot(&[(98.0, 1.0)], &[(99.5, 0.4)])).unwrap();
+    assert!(matches!(book.update("okx", "ETH-USDT", &snapshot(&[], &[])), Err(DepthError::InstrumentMismatch { .. })));
+
+    let bids = book.top_bids(2);
+    assert_eq!(bids[0].price, 100.0);
+    assert_eq!(bids[0].amount, 4.0);
+    assert_eq!(bids[0].venues.iter().map(|v| v.venue.as_str()).collect::<Vec<_>>(), vec!["binance", "okx"]);
+    assert_eq!(bids[1], ConsolidatedLevel { price: 99.0, amount: 2.0, venues: vec![VenueAmount { venue: "binance".into(), amount: 2.0 }] });
+
+    // Ties on price go to the larger amount
+    let bbo = book.bbo();
+    assert_eq!(bbo.bid.unwrap().venue, "okx");
+    assert_eq!(bbo.ask.unwrap(), VenueQuote { venue: "kraken".into(), price: 99.5, amount: 0.4 });
+
+    let crossings = book.crossings();
+    assert_eq!(crossings.len(), 2);
+    assert!(crossings.iter().all(|crossing| crossing.ask.venue == "kraken" && crossing.amount == 0.4));
+    assert!(crossings[0].edge_bps > 50.0 && crossings[0].edge_bps < 50.2);
+}
@@ -2,10 +2,16 @@ use std::collections::btree_map::BTreeMap;
 use std::fmt;
 // use std::sync::{Arc, RwLock};
 use serde::{de::Visitor, Deserialize, Deserializer, de::SeqAccess};
+use serde::de::DeserializeOwned;
+use serde::de::{DeserializeSeed, IgnoredAny, MapAccess};
 use ordered_float::OrderedFloat;
-use anyhow::{Result, Error, anyhow};
+use anyhow::Result;
+use crate::history::BookHistory;
+use crate::error::DepthError;
+use crate::source::{BookUpdate, Sequence};
+use crate::trades::{Side, TradeCorrelator};
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct Event {
     #[serde(rename = "e")]
     pub ttype: String,
@@ -27,12 +33,106 @@ impl Event {
     pub fn match_seq_num(&self, expected_id: &i64) -> bool {
         self.first_update_id == *expected_id
     }
+}
+
+/// `depthUpdate` of USD-M and COIN-M futures,
+/// which links each event to the previous one with `pu`
+#[derive(Deserialize, Debug, Clone)]
+pub struct FuturesEvent {
+    #[serde(rename = "e")]
+    pub ttype: String,
+    #[serde(rename = "E")]
+    pub ts: i64,
+    /// Transaction time
+    #[serde(rename = "T")]
+    pub transaction_time: i64,
+    #[serde(rename = "s")]
+    pub pair: String,
+    #[serde(rename = "U")]
+    pub first_update_id: i64,
+    #[serde(rename = "u")]
+    pub last_update_id: i64,
+    /// `u` of the previous event
+    #[serde(rename = "pu")]
+    pub previous_update_id: i64,
+    #[serde(rename = "b")]
+    pub bids: Vec<DepthRow>,
+    #[serde(rename = "a")]
+    pub asks: Vec<DepthRow>,
+}
+
+impl From<FuturesEvent> for Event {
+    fn from(event: FuturesEvent) -> Self {
+        Event {
+            ttype: event.ttype,
+            ts: event.ts,
+            pair: event.pair,
+            first_update_id: event.first_update_id,
+            last_update_id: event.last_update_id,
+            bids: event.bids,
+            asks: event.asks,
+        }
+    }
+}
+
+/// Futures partial depth streams send `depthUpdate` events too
+impl From<FuturesEvent> for LevelEvent {
+    fn from(event: FuturesEvent) -> Self {
+        LevelEvent {
+            last_update_id: event.last_update_id,
+            bids: event.bids,
+            asks: event.asks,
+        }
+    }
+}
+
+/// Diff depth event of a Binance market
+pub trait DepthUpdate: DeserializeOwned + Into<Event> + Send + 'static {
+    /// Event time `E`
+    fn event_time(&self) -> i64;
+
+    fn first_update_id(&self) -> i64;
+
+    fn last_update_id(&self) -> i64;
+
+    /// Id the book must be at for this event to apply:
+    /// `U - 1` on spot, `pu` on futures
+    fn previous_update_id(&self) -> i64;
+}
+
+impl DepthUpdate for Event {
+    fn event_time(&self) -> i64 {
+        self.ts
+    }
+
+    fn first_update_id(&self) -> i64 {
+        self.first_update_id
+    }
+
+    fn last_update_id(&self) -> i64 {
+        self.last_update_id
+    }
+
+    fn previous_update_id(&self) -> i64 {
+        self.first_update_id - 1
+    }
+}
+
+impl DepthUpdate for FuturesEvent {
+    fn event_time(&self) -> i64 {
+        self.ts
+    }
+
+    fn first_update_id(&self) -> i64 {
+        self.first_update_id
+    }
+
+    fn last_update_id(&self) -> i64 {
+        self.last_update_id
+    }
 
-    pub fn match_snapshot(&self, updated_id: i64) -> bool {
-        let first = self.first_update_id <= updated_id + 1;
-        let second = updated_id + 1 <= self.last_update_id;
-        println!("{}, {}", first, second);
-        self.first_update_id <= updated_id + 1 && updated_id + 1 <= self.last_update_id
+    fn previous_update_id(&self) -> i64 {
+        self.previous_update_id
     }
 }
 
@@ -105,7 +205,159 @@ impl<'de> Visitor<'de> for DepthRowVisitor {
 
 }
 
-#[derive(Deserialize)]
+/// Binance sends prices and quantities as strings, for `deserialize_with`
+pub(crate) fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let text = <&str>::deserialize(deserializer)?;
+    text.parse().map_err(serde::de::Error::custom)
+}
+
+/// Borrowed header of an `Event`, produced by `Shared::add_event_text`
+/// which applies the levels straight into the book while parsing.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EventRef<'a> {
+    pub ttype: &'a str,
+    pub ts: i64,
+    pub pair: &'a str,
+    pub first_update_id: i64,
+    pub last_update_id: i64,
+}
+
+/// Parse a depth message into `EventRef` with where it stands from `sequence`
+/// of its `U` and `u`, applying `b` and `a` to `book` only for `Sequence::Apply`.
+/// `trades` sees each level before it changes the book.
+struct ApplyEvent<'s, F> {
+    book: &'s mut Shared,
+    sequence: F,
+    trades: Option<&'s mut TradeCorrelator>,
+}
+
+impl<'de, 's, F: FnOnce(i64, i64) -> Sequence> DeserializeSeed<'de> for ApplyEvent<'s, F> {
+    type Value = (EventRef<'de>, Sequence);
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: Deserializer<'de>
+    {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de, 's, F: FnOnce(i64, i64) -> Sequence> Visitor<'de> for ApplyEvent<'s, F> {
+    type Value = (EventRef<'de>, Sequence);
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a depthUpdate event")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error> where A: MapAccess<'de> {
+        let ApplyEvent { book, sequence, mut trades } = self;
+        let mut sequence = Some(sequence);
+        let mut event = EventRef::default();
+        let mut first_update_id = None;
+        let mut last_update_id = None;
+        // Decided on the first levels, or at the end without levels
+        let mut found = None;
+
+        while let Some(key) = map.next_key::<&str>()? {
+            match key {
+                "e" => event.ttype = map.next_value()?,
+                "E" => event.ts = map.next_value()?,
+                "s" => event.pair = map.next_value()?,
+                "U" => first_update_id = Some(map.next_value()?),
+                "u" => last_update_id = Some(map.next_value()?),
+                "b" | "a" => {
+                    // Binance sends `E`, `U` and `u` before the levels,
+                    // so the sequence is known before the book is touched
+                    if found.is_none() {
+                        let first = first_update_id.ok_or_else(|| serde::de::Error::custom("Levels before U field"))?;
+                        let last = last_update_id.ok_or_else(|| serde::de::Error::custom("Missing u field"))?;
+                        let decided = sequence.take().map(|sequence| sequence(first, last)).unwrap_or(Sequence::Apply);
+                        if decided == Sequence::Apply {
+                            if let Some(trades) = trades.as_deref_mut() {
+                                trades.start_update(event.ts);
+                            }
+                        }
+                        found = Some(decided);
+                    }
+                    if found == Some(Sequence::Apply) {
+                        map.next_value_seed(ApplyLevels { book: &mut *book, trades: trades.as_deref_mut(), bids: key == "b" })?;
+                    } else {
+                        let _ = map.next_value::<IgnoredAny>()?;
+                    }
+                },
+                _ => {
+                    let _ = map.next_value::<IgnoredAny>()?;
+                },
+            }
+        }
+
+        match first_update_id {
+            Some(id) => event.first_update_id = id,
+            None => return Err(serde::de::Error::custom("Missing U field")),
+        }
+        match last_update_id {
+            Some(id) => event.last_update_id = id,
+            None => return Err(serde::de::Error::custom("Missing u field")),
+        }
+        // Without levels the sequence is only known now, nothing was applied
+        let found = match (found, sequence) {
+            (Some(found), _) => found,
+            (None, Some(sequence)) => sequence(event.first_update_id, event.last_update_id),
+            (None, None) => Sequence::Apply,
+        };
+        if found == Sequence::Apply {
+            if let Some(trades) = trades {
+                trades.finish_update(book);
+            }
+        }
+        Ok((event, found))
+    }
+}
+
+/// Apply each `DepthRow` of a level array to one side of `book`
+struct ApplyLevels<'s> {
+    book: &'s mut Shared,
+    trades: Option<&'s mut TradeCorrelator>,
+    bids: bool,
+}
+
+impl<'de, 's> DeserializeSeed<'de> for ApplyLevels<'s> {
+    type Value = ();
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+        where
+            D: Deserializer<'de>
+    {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, 's> Visitor<'de> for ApplyLevels<'s> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a list of [price, amount]")
+    }
+
+    fn visit_seq<A>(mut self, mut seq: A) -> Result<Self::Value, A::Error> where A: SeqAccess<'de> {
+        while let Some(row) = seq.next_element::<DepthRow>()? {
+            if self.bids {
+                if let Some(trades) = self.trades.as_deref_mut() {
+                    trades.on_level(Side::Bid, &row, self.book.bid_amount(row.price));
+                }
+                self.book.update_bid(row.price, row.amount);
+            } else {
+                if let Some(trades) = self.trades.as_deref_mut() {
+                    trades.on_level(Side::Ask, &row, self.book.ask_amount(row.price));
+                }
+                self.book.update_ask(row.price, row.amount);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct BinanceSnapshot {
     pub last_update_id: i64,
@@ -113,7 +365,7 @@ pub struct BinanceSnapshot {
     pub asks: Vec<DepthRow>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct BinanceSpotOrderBookSnapshot {
     pub last_update_id: i64,
@@ -133,8 +385,8 @@ impl BinanceSpotOrderBookSnapshot{
             }
         }
 
-        for ask in &other.bids{
-            if !self.asks.contains(&ask){
+        for ask in &other.asks{
+            if !self.asks.contains(ask){
                 contains_asks = false;
                 break
             }
@@ -143,6 +395,22 @@ impl BinanceSpotOrderBookSnapshot{
         contains_bids && contains_asks
     }
 
+    /// Book of COIN-M contracts worth `contract_size` USD each,
+    /// with amounts converted to base asset (e.g. BTC)
+    pub fn to_base_asset(&self, contract_size: f64) -> BinanceSpotOrderBookSnapshot {
+        let convert = |rows: &Vec<DepthRow>| {
+            rows.iter()
+                .map(|row| DepthRow { price: row.price, amount: row.amount * contract_size / row.price })
+                .collect()
+        };
+        BinanceSpotOrderBookSnapshot {
+            last_update_id: self.last_update_id,
+            time_stamp: self.time_stamp,
+            bids: convert(&self.bids),
+            asks: convert(&self.asks),
+        }
+    }
+
     /// Find different `bids` and `asks`,
     /// and return as `(bids, asks)`
     pub fn find_different(&self, other: &BinanceSpotOrderBookSnapshot) -> (Vec<DepthRow>, Vec<DepthRow>) {
@@ -155,8 +423,8 @@ impl BinanceSpotOrderBookSnapshot{
             }
         }
 
-        for ask in &other.bids{
-            if !self.asks.contains(&ask){
+        for ask in &other.asks{
+            if !self.asks.contains(ask){
                 ask_different.push(*ask);
             }
         }
@@ -165,20 +433,43 @@ impl BinanceSpotOrderBookSnapshot{
     }
 }
 
+#[cfg(test)]
+impl BinanceSpotOrderBookSnapshot {
+    /// Book at update id 1 and time 0 from `(price, amount)` levels
+    pub(crate) fn from_levels(bids: &[(f64, f64)], asks: &[(f64, f64)]) -> Self {
+        let rows = |levels: &[(f64, f64)]| levels.iter().map(|&(price, amount)| DepthRow { price, amount }).collect();
+        BinanceSpotOrderBookSnapshot { last_update_id: 1, time_stamp: 0, bids: rows(bids), asks: rows(asks) }
+    }
+}
+
 pub struct Shared {
     last_update_id: i64,
     time_stamp: i64,
     asks: BTreeMap<OrderedFloat<f64>, f64>,
     bids: BTreeMap<OrderedFloat<f64>, f64>,
+    history: BookHistory,
+}
+
+impl Default for Shared {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Shared {
     pub fn new() -> Self {
+        Self::with_history(0)
+    }
+
+    /// Keep the last `capacity` versions of the book,
+    /// `0` disables history
+    pub fn with_history(capacity: usize) -> Self {
         Shared {
             last_update_id: 0,
             time_stamp: 0,
             asks: BTreeMap::new(),
             bids: BTreeMap::new(),
+            history: BookHistory::new(capacity),
         }
     }
 
@@ -187,7 +478,23 @@ impl Shared {
         self.last_update_id
     }
 
+    /// Replace the book with a snapshot to (re)sync it. Updates may be missing
+    /// since the previous version, so the history is dropped rather than
+    /// answering point-in-time queries across the gap
     pub fn load_snapshot(&mut self, snapshot: &BinanceSnapshot) {
+        self.clear_history();
+        self.set_levels(snapshot);
+    }
+
+    /// Replace the book with a snapshot the venue sent in line with its updates,
+    /// recorded as a new version at `time_stamp`
+    pub fn apply_snapshot(&mut self, snapshot: &BinanceSnapshot, time_stamp: i64) {
+        self.set_levels(snapshot);
+        self.time_stamp = time_stamp;
+        self.record_history();
+    }
+
+    fn set_levels(&mut self, snapshot: &BinanceSnapshot) {
         self.asks.clear();
         for ask in &snapshot.asks {
             self.asks.insert(OrderedFloat(ask.price), ask.amount);
@@ -201,48 +508,169 @@ impl Shared {
         self.last_update_id = snapshot.last_update_id;
     }
 
+    /// Number of `(bids, asks)` levels
+    pub fn level_counts(&self) -> (usize, usize) {
+        (self.bids.len(), self.asks.len())
+    }
+
+    /// Amount of the bid at `price`, `None` without such level
+    pub fn bid_amount(&self, price: f64) -> Option<f64> {
+        self.bids.get(&OrderedFloat(price)).copied()
+    }
+
+    /// Amount of the ask at `price`, `None` without such level
+    pub fn ask_amount(&self, price: f64) -> Option<f64> {
+        self.asks.get(&OrderedFloat(price)).copied()
+    }
+
+    /// Set amount of the ask at `price`, zero `amount` removes the level
+    pub fn update_ask(&mut self, price: f64, amount: f64) {
+        if amount == 0.0 {
+            self.asks.remove(&OrderedFloat(price));
+        } else {
+            self.asks.insert(OrderedFloat(price), amount);
+        }
+    }
+
+    /// Set amount of the bid at `price`, zero `amount` removes the level
+    pub fn update_bid(&mut self, price: f64, amount: f64) {
+        if amount == 0.0 {
+            self.bids.remove(&OrderedFloat(price));
+        } else {
+            self.bids.insert(OrderedFloat(price), amount);
+        }
+    }
+
+    /// Keep only the best `depth` levels of each side
+    pub fn truncate(&mut self, depth: usize) {
+        while self.asks.len() > depth {
+            self.asks.pop_last();
+        }
+        while self.bids.len() > depth {
+            self.bids.pop_first();
+        }
+    }
+
+    /// Best `n` asks, lowest price first
+    pub fn top_asks(&self, n: usize) -> Vec<DepthRow> {
+        self.asks
+            .iter()
+            .take(n)
+            .map(|(price, amount)| DepthRow {price: price.into_inner(), amount: *amount})
+            .collect()
+    }
+
+    /// Best `n` bids, highest price first
+    pub fn top_bids(&self, n: usize) -> Vec<DepthRow> {
+        self.bids
+            .iter()
+            .rev()
+            .take(n)
+            .map(|(price, amount)| DepthRow {price: price.into_inner(), amount: *amount})
+            .collect()
+    }
+
     /// Only used for "Event"
     pub fn add_event(&mut self, event: Event) {
         for ask in event.asks {
-            if ask.amount == 0.0 {
-                self.asks.remove(&OrderedFloat(ask.price));
-            } else {
-                self.asks.insert(OrderedFloat(ask.price), ask.amount);
-            }
+            self.update_ask(ask.price, ask.amount);
         }
 
         for bid in event.bids {
-            if bid.amount == 0.0 {
-                self.bids.remove(&OrderedFloat(bid.price));
-            } else {
-                self.bids.insert(OrderedFloat(bid.price), bid.amount);
-            }
+            self.update_bid(bid.price, bid.amount);
         }
 
         self.last_update_id = event.last_update_id;
         self.time_stamp = event.ts;
+        self.record_history();
+    }
+
+    /// Apply an update normalized by an `OrderBookSource`
+    pub fn apply_update(&mut self, update: BookUpdate) {
+        for ask in update.asks {
+            self.update_ask(ask.price, ask.amount);
+        }
+
+        for bid in update.bids {
+            self.update_bid(bid.price, bid.amount);
+        }
+
+        self.last_update_id = update.last_update_id;
+        self.time_stamp = update.event_time;
+        self.record_history();
+    }
+
+    /// Borrowed counterpart of `add_event`, parse a depth message and apply its levels
+    /// straight into the book without building an `Event`.
+    /// A malformed level leaves the book partially updated, it should be resynced.
+    pub fn add_event_text<'a>(&mut self, text: &'a str) -> Result<EventRef<'a>, DepthError> {
+        self.apply_event_text(text, |_, _| Sequence::Apply, None).map(|(event, _)| event)
+    }
+
+    /// Borrowed counterpart of `update_snapshot`,
+    /// the book is left untouched if the event doesn't follow it
+    pub fn update_snapshot_text<'a>(&mut self, text: &'a str) -> Result<EventRef<'a>, DepthError> {
+        let expected = self.last_update_id + 1;
+        let follows = |first_update_id, _| if first_update_id == expected { Sequence::Apply } else { Sequence::Gap };
+        match self.apply_event_text(text, follows, None)? {
+            (event, Sequence::Apply) => Ok(event),
+            (event, _) => Err(DepthError::SequenceGap { expected, found: event.first_update_id }),
+        }
+    }
+
+    /// Parse a depth message in one pass, applying its levels only if `sequence`
+    /// of its `U` and `u` is `Sequence::Apply`, and `trades` attributing them.
+    /// The event is returned with where it stood, the book is untouched otherwise.
+    pub fn apply_event_text<'a>(
+        &mut self,
+        text: &'a str,
+        sequence: impl FnOnce(i64, i64) -> Sequence,
+        trades: Option<&mut TradeCorrelator>,
+    ) -> Result<(EventRef<'a>, Sequence), DepthError> {
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        let (event, sequence) = ApplyEvent { book: self, sequence, trades }
+            .deserialize(&mut deserializer)
+            .and_then(|event| deserializer.end().map(|_| event))
+            .map_err(|e| DepthError::parse(text, e))?;
+
+        if sequence == Sequence::Apply {
+            self.last_update_id = event.last_update_id;
+            self.time_stamp = event.ts;
+            self.record_history();
+        }
+        Ok((event, sequence))
     }
 
     /// Only used for "LevelEvent"
     pub fn set_level_event(&mut self, level_event: LevelEvent, time_stamp: i64){
         for ask in level_event.asks {
-            if ask.amount == 0.0 {
-                self.asks.remove(&OrderedFloat(ask.price));
-            } else {
-                self.asks.insert(OrderedFloat(ask.price), ask.amount);
-            }
+            self.update_ask(ask.price, ask.amount);
         }
 
         for bid in level_event.bids {
-            if bid.amount == 0.0 {
-                self.bids.remove(&OrderedFloat(bid.price));
-            } else {
-                self.bids.insert(OrderedFloat(bid.price), bid.amount);
-            }
+            self.update_bid(bid.price, bid.amount);
         }
 
         self.last_update_id = level_event.last_update_id;
         self.time_stamp = time_stamp;
+        self.record_history();
+    }
+
+    /// Store current book as a new version in history
+    fn record_history(&mut self) {
+        if self.history.is_enabled() {
+            let snapshot = self.get_snapshot();
+            self.history.push(snapshot);
+        }
+    }
+
+    pub fn history(&self) -> &BookHistory {
+        &self.history
+    }
+
+    /// Drop the recorded versions, e.g. when the book resyncs
+    pub fn clear_history(&mut self) {
+        self.history.clear();
     }
 
     pub fn get_snapshot(&self) -> BinanceSpotOrderBookSnapshot {
@@ -267,13 +695,12 @@ impl Shared {
 
     /// With give event to update snapshot,
     /// if event doesn't satisfy return error
-    pub fn update_snapshot(&mut self, event: Event)-> Result<()>  {
+    pub fn update_snapshot(&mut self, event: Event)-> Result<(), DepthError>  {
         if event.first_update_id != self.last_update_id + 1 {
-            Err(anyhow!(
-                "Expect event u to be {}, found {}",
-                self.last_update_id + 1,
-                event.first_update_id
-            ))
+            Err(DepthError::SequenceGap {
+                expected: self.last_update_id + 1,
+                found: event.first_update_id,
+            })
         } else{
             self.add_event(event);
             Ok(())
@@ -287,4 +714,94 @@ fn depth_row(){
     let a = DepthRow{amount:1.0, price: 2.0};
     let b = DepthRow{amount:1.0, price: 2.0};
     assert_eq!(a, b);
-}
\ No newline at end of file
+}
+
+#[test]
+fn add_event_text_matches_add_event(){
+    let text = r#"{"e":"depthUpdate","E":1000,"s":"BNBBTC","U":11,"u":12,"b":[["9.5","0.0"],["9.7","4.0"]],"a":[["10.0","2.0"]]}"#;
+    let mut owned = Shared::new();
+    let mut borrowed = Shared::new();
+    owned.add_event(serde_json::from_str(text).unwrap());
+    let event = borrowed.add_event_text(text).unwrap();
+
+    assert_eq!(event.pair, "BNBBTC");
+    assert_eq!((event.first_update_id, event.last_update_id), (11, 12));
+    assert_eq!(borrowed.get_snapshot().bids, owned.get_snapshot().bids);
+    assert_eq!(borrowed.get_snapshot().asks, owned.get_snapshot().asks);
+
+    // U must follow the book, 14 != 12 + 1
+    let gap = r#"{"e":"depthUpdate","E":1100,"s":"BNBBTC","U":14,"u":15,"b":[["9.7","0.0"]],"a":[]}"#;
+    assert!(matches!(
+        borrowed.update_snapshot_text(gap),
+        Err(DepthError::SequenceGap { expected: 13, found: 14 })
+    ));
+    assert_eq!(borrowed.id(), 12);
+    assert_eq!(borrowed.get_snapshot().bids, owned.get_snapshot().bids);
+
+    // Gap found without levels to check it on
+    let empty_gap = r#"{"e":"depthUpdate","E":1100,"s":"BNBBTC","U":14,"u":15}"#;
+    assert!(matches!(
+        borrowed.update_snapshot_text(empty_gap),
+        Err(DepthError::SequenceGap { expected: 13, found: 14 })
+    ));
+    let no_u = r#"{"e":"depthUpdate","E":1100,"s":"BNBBTC","U":13,"b":[],"a":[]}"#;
+    assert!(matches!(borrowed.update_snapshot_text(no_u), Err(DepthError::Parse { .. })));
+    assert_eq!(borrowed.id(), 12);
+}
+
+#[test]
+fn load_snapshot_drops_history(){
+    let text = r#"{"e":"depthUpdate","E":1000,"s":"BNBBTC","U":11,"u":12,"b":[["9.5","1.0"]],"a":[]}"#;
+    let mut book = Shared::with_history(4);
+    book.add_event(serde_json::from_str(text).unwrap());
+    assert_eq!(book.history().len(), 1);
+
+    // Resync after a gap, the version at 12 may not hold anymore
+    book.load_snapshot(&BinanceSnapshot { last_update_id: 20, bids: vec![], asks: vec![] });
+    assert!(book.history().is_empty());
+    assert!(book.history().at_time(1000).is_none());
+
+    // Snapshots sent on the stream are versions of their own
+    book.apply_snapshot(&BinanceSnapshot { last_update_id: 21, bids: vec![DepthRow { price: 9.0, amount: 1.0 }], asks: vec![] }, 2000);
+    assert_eq!(book.get_snapshot().time_stamp, 2000);
+    assert_eq!(book.history().at_time(2500).unwrap().last_update_id, 21);
+}
+
+#[test]
+fn compare_asks_with_asks(){
+    let book = |asks: Vec<DepthRow>| BinanceSpotOrderBookSnapshot {
+        last_update_id: 1,
+        time_stamp: 0,
+        bids: vec![DepthRow { price: 9.0, amount: 1.0 }],
+        asks,
+    };
+    let depth = book(vec![DepthRow { price: 10.0, amount: 1.0 }, DepthRow { price: 11.0, amount: 2.0 }]);
+    assert!(depth.if_contains(&book(vec![DepthRow { price: 10.0, amount: 1.0 }])));
+
+    let level = book(vec![DepthRow { price: 10.0, amount: 3.0 }]);
+    assert!(!depth.if_contains(&level));
+    assert_eq!(depth.find_different(&level), (vec![], vec![DepthRow { price: 10.0, amount: 3.0 }]));
+}
+
+#[test]
+fn contracts_to_base_asset(){
+    let snapshot = BinanceSpotOrderBookSnapshot {
+        last_update_id: 1,
+        time_stamp: 0,
+        bids: vec![DepthRow { price: 50000.0, amount: 10.0 }],
+        asks: vec![DepthRow { price: 40000.0, amount: 4.0 }],
+    };
+    // 10 contracts of 100 USD at 50000 USD
+    let base = snapshot.to_base_asset(100.0);
+    assert_eq!(base.bids[0], DepthRow { price: 50000.0, amount: 0.02 });
+    assert_eq!(base.asks[0], DepthRow { price: 40000.0, amount: 0.01 });
+}
+
+#[test]
+fn add_event_text_reports_payload(){
+    let text = r#"{"e":"depthUpdate","E":1000,"s":"BNBBTC","U":11,"u":12,"b":[["9.5","x"]],"a":[]}"#;
+    match Shared::new().add_event_text(text) {
+        Err(DepthError::Parse { payload, .. }) => assert_eq!(payload, text),
+        other => panic!("Expect parse error, found {:?}", other),
+    }
+}
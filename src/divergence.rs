@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+use ordered_float::OrderedFloat;
+use crate::deep::{BinanceSpotOrderBookSnapshot, DepthRow};
+
+/// Windows reported by default
+pub const DEFAULT_WINDOWS: [Duration; 3] = [Duration::from_secs(60), Duration::from_secs(300), Duration::from_secs(3600)];
+/// Samples kept, an hour of comparisons once a second
+pub const DEFAULT_MAX_SAMPLES: usize = 3600;
+
+/// How far the levels of one book are from a reference book at one comparison
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Divergence {
+    /// Time of the comparison in ms
+    pub time_stamp: i64,
+    /// Levels not found with the same amount in the other book, reference
+    /// levels count within the price range of the compared book only
+    pub differing_levels: usize,
+    /// Largest distance from a differing level to the closest price of the
+    /// other book on its side, 0 when only amounts differ
+    pub max_price_distance: f64,
+    /// Sum of the amount differences of differing levels,
+    /// a level missing from one of the books counts its whole amount
+    pub qty_error: f64,
+}
+
+impl Divergence {
+    /// Levels of `book` (e.g. depth20) against `reference` (e.g. the diff book)
+    pub fn between(reference: &BinanceSpotOrderBookSnapshot, book: &BinanceSpotOrderBookSnapshot, time_stamp: i64) -> Self {
+        let mut divergence = Divergence { time_stamp, ..Default::default() };
+        divergence.add_side(&reference.bids, &book.bids);
+        divergence.add_side(&reference.asks, &book.asks);
+        divergence
+    }
+
+    /// Both ways within the price range of `book`, so a stale reference level
+    /// between two levels of `book` counts as well as a wrong level of `book`
+    fn add_side(&mut self, reference: &[DepthRow], book: &[DepthRow]) {
+        let levels: BTreeMap<OrderedFloat<f64>, f64> = reference.iter().map(|row| (OrderedFloat(row.price), row.amount)).collect();
+        let book_levels: BTreeMap<OrderedFloat<f64>, f64> = book.iter().map(|row| (OrderedFloat(row.price), row.amount)).collect();
+        for row in book {
+            let amount = levels.get(&OrderedFloat(row.price)).copied();
+            if amount == Some(row.amount) {
+                continue
+            }
+            self.add_level(row.amount - amount.unwrap_or(0.0), closest_distance(&levels, row.price));
+        }
+
+        let (low, high) = match (book_levels.keys().next(), book_levels.keys().next_back()) {
+            (Some(low), Some(high)) => (*low, *high),
+            _ => return,
+        };
+        for (price, amount) in levels.range(low..=high) {
+            if !book_levels.contains_key(price) {
+                self.add_level(*amount, closest_distance(&book_levels, price.into_inner()));
+            }
+        }
+    }
+
+    fn add_level(&mut self, qty_error: f64, distance: f64) {
+        self.differing_levels += 1;
+        self.qty_error += qty_error.abs();
+        self.max_price_distance = self.max_price_distance.max(distance);
+    }
+
+    pub fn is_diverged(&self) -> bool {
+        self.differing_levels > 0
+    }
+}
+
+/// Distance from `price` to the closest price of `levels`, 0 when it is one of them
+fn closest_distance(levels: &BTreeMap<OrderedFloat<f64>, f64>, price: f64) -> f64 {
+    let below = levels.range(..=OrderedFloat(price)).next_back().map(|(closest, _)| price - closest.into_inner());
+    let above = levels.range(OrderedFloat(price)..).next().map(|(closest, _)| closest.into_inner() - price);
+    match (below, above) {
+        (Some(below), Some(above)) => below.min(above),
+        (distance, None) | (None, distance) => distance.unwrap_or(0.0),
+    }
+}
+
+/// Nearest rank percentiles of a window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Percentiles {
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+impl Percentiles {
+    /// `None` without values
+    fn of(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None
+        }
+        values.sort_by(f64::total_cmp);
+        let percentile = |p: usize| values[((values.len() * p).div_ceil(100)).max(1) - 1];
+        Some(Percentiles {
+            min: values[0],
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: values[values.len() - 1],
+        })
+    }
+}
+
+/// Divergence over the comparisons of a window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowStats {
+    pub window: Duration,
+    pub comparisons: usize,
+    /// Comparisons with differing levels
+    pub diverged: usize,
+    pub differing_levels: Percentiles,
+    pub max_price_distance: Percentiles,
+    pub qty_error: Percentiles,
+    /// Most consecutive diverged comparisons within the window
+    pub longest_streak: usize,
+}
+
+/// Divergence of every comparison of two books, summarized over rolling windows
+pub struct DivergenceStats {
+    windows: Vec<Duration>,
+    max_samples: usize,
+    samples: VecDeque<Divergence>,
+    comparisons: u64,
+    diverged: u64,
+    /// Consecutive diverged comparisons up to the latest one
+    current_streak: usize,
+    longest_streak: usize,
+}
+
+impl Default for DivergenceStats {
+    fn default() -> Self {
+        Self::new(&DEFAULT_WINDOWS, DEFAULT_MAX_SAMPLES)
+    }
+}
+
+impl DivergenceStats {
+    /// Keep at most `max_samples` comparisons, windows longer than
+    /// they cover only see the samples kept
+    pub fn new(windows: &[Duration], max_samples: usize) -> Self {
+        DivergenceStats {
+            windows: windows.to_vec(),
+            max_samples,
+            samples: VecDeque::new(),
+            comparisons: 0,
+            diverged: 0,
+            current_streak: 0,
+            longest_streak: 0,
+        }
+    }
+
+    pub fn record(&mut self, divergence: Divergence) {
+        if self.samples.len() == self.max_samples {
+            let _ = self.samples.pop_front();
+        }
+        self.comparisons += 1;
+        if divergence.is_diverged() {
+            self.diverged += 1;
+            self.current_streak += 1;
+            self.longest_streak = self.longest_streak.max(self.current_streak);
+        } else {
+            self.current_streak = 0;
+        }
+        self.samples.push_back(divergence);
+    }
+
+    /// Comparisons recorded since start
+    pub fn comparisons(&self) -> u64 {
+        self.comparisons
+    }
+
+    /// Diverged comparisons recorded since start
+    pub fn diverged(&self) -> u64 {
+        self.diverged
+    }
+
+    pub fn current_streak(&self) -> usize {
+        self.current_streak
+    }
+
+    /// Most consecutive diverged comparisons since start
+    pub fn longest_streak(&self) -> usize {
+        self.longest_streak
+    }
+
+    /// Comparisons of the last `window` before the latest one,
+    /// `None` until a comparison is recorded or for an empty window
+    pub fn window(&self, window: Duration) -> Option<WindowStats> {
+        let latest = self.samples.back()?.time_stamp;
+        let since = latest - window.as_millis() as i64;
+        let samples: Vec<&Divergence> = self.samples.iter().filter(|sample| sample.time_stamp > since).collect();
+
+        let mut streak = 0;
+        let mut longest_streak = 0;
+        for sample in &samples {
+            streak = if sample.is_diverged() { streak + 1 } else { 0 };
+            longest_streak = longest_streak.max(streak);
+        }
+        Some(WindowStats {
+            window,
+            comparisons: samples.len(),
+            diverged: samples.iter().filter(|sample| sample.is_diverged()).count(),
+            differing_levels: Percentiles::of(samples.iter().map(|sample| sample.differing_levels as f64).collect())?,
+            max_price_distance: Percentiles::of(samples.iter().map(|sample| sample.max_price_distance).collect())?,
+            qty_error: Percentiles::of(samples.iter().map(|sample| sample.qty_error).collect())?,
+            longest_streak,
+        })
+    }
+
+    /// Statistics of every configured window
+    pub fn report(&self) -> Vec<WindowStats> {
+        self.windows.iter().filter_map(|window| self.window(*window)).collect()
+    }
+}
+
+/// Summary to print, e.g. on shutdown
+impl fmt::Display for DivergenceStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{} comparisons, {} diverged, longest streak {}, current streak {}",
+            self.comparisons, self.diverged, self.longest_streak, self.current_streak
+        )?;
+        for stats in self.report() {
+            writeln!(
+                f,
+                "last {}s: {}/{} diverged, longest streak {}, differing levels p50 {} p99 {} max {}, \
+                 price distance p50 {} p99 {} max {}, qty error p50 {} p99 {} max {}",
+                stats.window.as_secs(),
+                stats.diverged,
+                stats.comparisons,
+                stats.longest_streak,
+                stats.differing_levels.p50,
+                stats.differing_levels.p99,
+                stats.differing_levels.max,
+                stats.max_price_distance.p50,
+                stats.max_price_distance.p99,
+                stats.max_price_distance.max,
+                stats.qty_error.p50,
+                stats.qty_error.p99,
+                stats.qty_error.max,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn divergence_windows_and_streaks(){
+    let snapshot = |bids: &[(f64, f64)]| BinanceSpotOrderBookSnapshot::from_levels(bids, &[]);
+    let reference = snapshot(&[(10.0, 1.0), (9.0, 2.0), (7.0, 1.0)]);
+    let divergence = Divergence::between(&reference, &snapshot(&[(10.0, 1.5), (9.0, 2.0), (7.5, 3.0)]), 0);
+    assert_eq!(divergence.differing_levels, 2);
+    assert_eq!(divergence.max_price_distance, 0.5);
+    assert_eq!(divergence.qty_error, 3.5);
+    // A stale reference level between two depth20 prices, the 7 below them is not compared
+    let stale = snapshot(&[(10.0, 1.0), (9.0, 2.0), (8.5, 0.4), (8.0, 1.0), (7.0, 1.0)]);
+    let divergence = Divergence::between(&stale, &snapshot(&[(10.0, 1.0), (9.0, 2.0), (8.0, 1.0)]), 0);
+    assert_eq!(divergence.differing_levels, 1);
+    assert_eq!(divergence.max_price_distance, 0.5);
+    assert_eq!(divergence.qty_error, 0.4);
+
+    let mut stats = DivergenceStats::new(&[Duration::from_secs(3)], 10);
+    assert!(stats.window(Duration::from_secs(3)).is_none());
+    for (second, differing_levels) in [0, 2, 3, 0, 1, 4, 5].into_iter().enumerate() {
+        stats.record(Divergence { time_stamp: second as i64 * 1000, differing_levels, ..Default::default() });
+    }
+
+    assert_eq!((stats.comparisons(), stats.diverged()), (7, 5));
+    assert_eq!((stats.longest_streak(), stats.current_streak()), (3, 3));
+    // Seconds 4 to 6
+    let window = stats.report()[0];
+    assert_eq!((window.comparisons, window.diverged, window.longest_streak), (3, 3, 3));
+    assert_eq!((window.differing_levels.min, window.differing_levels.p50, window.differing_levels.max), (1.0, 4.0, 5.0));
+    // No comparison within a zero window
+    assert!(stats.window(Duration::ZERO).is_none());
+    assert!(stats.to_string().starts_with("7 comparisons, 5 diverged, longest streak 3"));
+}
@@ -0,0 +1,68 @@
+use std::time::Duration;
+use thiserror::Error;
+
+/// Failures while keeping an order book in sync
+#[derive(Error, Debug)]
+pub enum DepthError {
+    /// Event doesn't continue the book, a new snapshot is needed
+    #[error("Expect event U to be {expected}, found {found}")]
+    SequenceGap { expected: i64, found: i64 },
+
+    /// Book doesn't match the checksum sent by the venue
+    #[error("Checksum mismatch, expect {expected}, found {found}")]
+    ChecksumMismatch { expected: u32, found: u32 },
+
+    /// No snapshot received on the stream yet
+    #[error("No snapshot received on the stream")]
+    MissingSnapshot,
+
+    /// Venue refused a subscription or reported an error on the stream
+    #[error("Venue error: {0}")]
+    Venue(String),
+
+    /// Every buffered event is newer than the snapshot
+    #[error("Snapshot {last_update_id} is older than event {first_update_id}, need a new snap shot")]
+    StaleSnapshot { last_update_id: i64, first_update_id: i64 },
+
+    /// Message couldn't be parsed, `payload` is the offending text
+    #[error("Fail to parse {payload:?}: {source}")]
+    Parse { payload: String, source: serde_json::Error },
+
+    #[error("Websocket error: {0}")]
+    Transport(Box<tokio_tungstenite::tungstenite::Error>),
+
+    #[error("Request error: {0}")]
+    Request(#[from] reqwest::Error),
+
+    /// REST endpoint answered with a non success status
+    #[error("HTTP status {status}: {body}")]
+    HttpStatus { status: u16, body: String },
+
+    /// Binance rejected the request with an error body
+    #[error("Binance error {code} (HTTP {status}): {msg}")]
+    Api { status: u16, code: i64, msg: String },
+
+    /// Request weight exceeded (429) or IP banned (418)
+    #[error("Rate limited (HTTP {status}), retry after {retry_after:?}")]
+    RateLimited { status: u16, retry_after: Option<Duration> },
+
+    /// Symbol not listed by the exchange
+    #[error("Unknown symbol {0}")]
+    UnknownSymbol(String),
+
+    /// Book of another instrument than the one being consolidated
+    #[error("Expect instrument {expected}, found {found}")]
+    InstrumentMismatch { expected: String, found: String },
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for DepthError {
+    fn from(error: tokio_tungstenite::tungstenite::Error) -> Self {
+        DepthError::Transport(Box::new(error))
+    }
+}
+
+impl DepthError {
+    pub fn parse(payload: &str, source: serde_json::Error) -> Self {
+        DepthError::Parse { payload: payload.to_string(), source }
+    }
+}
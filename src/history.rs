@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+use crate::deep::BinanceSpotOrderBookSnapshot;
+
+/// A minute of 100ms depth events, a sensible capacity for `BinanceSpotOrderBook::with_history`
+pub const DEFAULT_HISTORY_CAPACITY: usize = 600;
+
+/// Bounded ring of past order book states,
+/// oldest in front, newest at the back.
+pub struct BookHistory {
+    capacity: usize,
+    states: VecDeque<BinanceSpotOrderBookSnapshot>,
+}
+
+impl BookHistory {
+    /// History keeping at most `capacity` versions,
+    /// `0` disables recording
+    pub fn new(capacity: usize) -> Self {
+        BookHistory {
+            capacity,
+            states: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Record a new book version, dropping the oldest one when full
+    pub fn push(&mut self, snapshot: BinanceSpotOrderBookSnapshot) {
+        if !self.is_enabled() {
+            return
+        }
+
+        if self.states.len() == self.capacity {
+            let _ = self.states.pop_front();
+        }
+        self.states.push_back(snapshot);
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+
+    /// Book as it was once every update up to `update_id` was applied,
+    /// i.e. the newest version with `last_update_id <= update_id`.
+    /// Return `None` if `update_id` is older than the retained history.
+    pub fn at_update_id(&self, update_id: i64) -> Option<&BinanceSpotOrderBookSnapshot> {
+        self.states
+            .iter()
+            .rev()
+            .find(|state| state.last_update_id <= update_id)
+    }
+
+    /// Book as it was at `time_stamp` (ms),
+    /// i.e. the newest version with `time_stamp <= time_stamp`.
+    /// Return `None` if `time_stamp` is older than the retained history.
+    pub fn at_time(&self, time_stamp: i64) -> Option<&BinanceSpotOrderBookSnapshot> {
+        self.states
+            .iter()
+            .rev()
+            .find(|state| state.time_stamp <= time_stamp)
+    }
+}
+
+#[cfg(test)]
+fn version(last_update_id: i64, time_stamp: i64) -> BinanceSpotOrderBookSnapshot {
+    BinanceSpotOrderBookSnapshot {
+        last_update_id,
+        time_stamp,
+        bids: Vec::new(),
+        asks: Vec::new(),
+    }
+}
+
+#[test]
+fn history_drops_oldest_version(){
+    let mut history = BookHistory::new(2);
+    history.push(version(10, 1000));
+    history.push(version(12, 1100));
+    history.push(version(15, 1200));
+
+    assert_eq!(history.len(), 2);
+    assert!(history.at_update_id(11).is_none());
+    assert_eq!(history.at_update_id(12).unwrap().last_update_id, 12);
+}
+
+#[test]
+fn history_point_in_time_queries(){
+    let mut history = BookHistory::new(10);
+    history.push(version(10, 1000));
+    history.push(version(12, 1100));
+    history.push(version(15, 1200));
+
+    assert_eq!(history.at_update_id(14).unwrap().last_update_id, 12);
+    assert_eq!(history.at_update_id(100).unwrap().last_update_id, 15);
+    assert_eq!(history.at_time(1199).unwrap().last_update_id, 12);
+    assert!(history.at_time(999).is_none());
+
+    let mut disabled = BookHistory::new(0);
+    disabled.push(version(10, 1000));
+    assert!(disabled.is_empty());
+}
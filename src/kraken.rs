@@ -0,0 +1,253 @@
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use serde_json::{json, Map, Value};
+use url::Url;
+use crate::deep::{BinanceSnapshot, DepthRow, Shared};
+use crate::error::DepthError;
+use crate::source::{malformed, BookUpdate, OrderBookSource, SourceMessage};
+
+const STREAM: &str = "wss://ws.kraken.com";
+pub const DEFAULT_DEPTH: usize = 10;
+/// Levels per side covered by the checksum
+const CHECKSUM_DEPTH: usize = 10;
+/// The book feed isn't sent on an interval, each change goes out as it happens
+/// a few levels at a time, so busy pairs send many messages a second
+const BUFFER_CAPACITY: usize = 1000;
+
+/// Book feed of a Kraken pair (e.g. "XBT/USD"), checked against the CRC32
+/// checksum Kraken sends with every update.
+/// Kraken has no update ids, messages are numbered as they are parsed.
+pub struct KrakenSource {
+    pair: String,
+    depth: usize,
+    /// Id of the last parsed message
+    sequence: AtomicI64,
+    /// Decimals of prices and volumes as sent in the snapshot,
+    /// needed to format levels for the checksum
+    price_decimals: AtomicUsize,
+    volume_decimals: AtomicUsize,
+}
+
+impl KrakenSource {
+    pub fn new(pair: &str) -> Self {
+        Self::with_depth(pair, DEFAULT_DEPTH)
+    }
+
+    /// `depth` is one of 10, 25, 100, 500 or 1000
+    pub fn with_depth(pair: &str, depth: usize) -> Self {
+        KrakenSource {
+            pair: pair.to_string(),
+            depth,
+            sequence: AtomicI64::new(0),
+            price_decimals: AtomicUsize::new(0),
+            volume_decimals: AtomicUsize::new(0),
+        }
+    }
+
+    fn next_id(&self) -> i64 {
+        self.sequence.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Checksum of the top levels of `book` as Kraken computes it
+    pub fn checksum(&self, book: &Shared) -> u32 {
+        checksum(
+            book,
+            self.price_decimals.load(Ordering::SeqCst),
+            self.volume_decimals.load(Ordering::SeqCst),
+        )
+    }
+}
+
+/// CRC32 of the top 10 asks, lowest first, then the top 10 bids, highest first.
+/// Each level is its price then its volume, formatted with all the decimals
+/// of the pair, without the decimal point and leading zeros.
+pub fn checksum(book: &Shared, price_decimals: usize, volume_decimals: usize) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for row in book.top_asks(CHECKSUM_DEPTH).iter().chain(book.top_bids(CHECKSUM_DEPTH).iter()) {
+        hasher.update(checksum_field(row.price, price_decimals).as_bytes());
+        hasher.update(checksum_field(row.amount, volume_decimals).as_bytes());
+    }
+    hasher.finalize()
+}
+
+fn checksum_field(value: f64, decimals: usize) -> String {
+    let text = format!("{:.*}", decimals, value).replace('.', "");
+    text.trim_start_matches('0').to_string()
+}
+
+fn decimals(number: &str) -> usize {
+    number.split_once('.').map_or(0, |(_, decimals)| decimals.len())
+}
+
+/// Levels of one side and what they tell about the message
+#[derive(Default)]
+struct Levels {
+    rows: Vec<DepthRow>,
+    /// Latest level timestamp, in ms
+    time: i64,
+    /// Decimals of the first `(price, volume)`
+    decimals: Option<(usize, usize)>,
+}
+
+/// Read `[[price, volume, timestamp, ("r")], ...]`
+fn read_levels(text: &str, value: &Value, levels: &mut Levels) -> Result<(), DepthError> {
+    let rows = value.as_array().ok_or_else(|| malformed(text, "Expect a list of levels"))?;
+    for row in rows {
+        let fields: Vec<&str> = row.as_array()
+            .map(|fields| fields.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let (price, volume, time) = match fields[..] {
+            [price, volume, time, ..] => (price, volume, time),
+            _ => return Err(malformed(text, "Expect [price, volume, timestamp]")),
+        };
+        let number = |field: &str| field.parse::<f64>().map_err(|_| malformed(text, "Bad number in level"));
+
+        levels.rows.push(DepthRow { price: number(price)?, amount: number(volume)? });
+        levels.time = levels.time.max((number(time)? * 1000.0) as i64);
+        levels.decimals.get_or_insert((decimals(price), decimals(volume)));
+    }
+    Ok(())
+}
+
+/// `{"event": ...}` messages: status, heartbeats and subscription results
+fn event_message(text: &str, event: &Map<String, Value>) -> Result<Option<SourceMessage>, DepthError> {
+    let failed = match event.get("event").and_then(Value::as_str) {
+        Some("error") => true,
+        Some("subscriptionStatus") => event.get("status").and_then(Value::as_str) == Some("error"),
+        _ => false,
+    };
+    if failed {
+        let msg = event.get("errorMessage").and_then(Value::as_str).unwrap_or(text);
+        return Err(DepthError::Venue(msg.to_string()))
+    }
+    Ok(None)
+}
+
+impl OrderBookSource for KrakenSource {
+    fn stream(&self) -> String {
+        format!("kraken/book-{}", self.depth)
+    }
+
+    fn stream_url(&self) -> Url {
+        Url::parse(STREAM).expect("Bad URL")
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        let subscribe = json!({
+            "event": "subscribe",
+            "pair": [self.pair],
+            "subscription": {"name": "book", "depth": self.depth},
+        });
+        vec![subscribe.to_string()]
+    }
+
+    fn parse(&self, text: &str) -> Result<Option<SourceMessage>, DepthError> {
+        let message: Value = serde_json::from_str(text).map_err(|e| DepthError::parse(text, e))?;
+        let items = match message {
+            Value::Array(items) => items,
+            Value::Object(event) => return event_message(text, &event),
+            _ => return Err(malformed(text, "Expect a channel message or an event")),
+        };
+
+        // [channelID, data, (data), channelName, pair]
+        if items.len() < 4 {
+            return Err(malformed(text, "Expect [channelID, data, channelName, pair]"))
+        }
+        let channel = items[items.len() - 2].as_str().unwrap_or_default();
+        if !channel.starts_with("book") {
+            return Ok(None)
+        }
+
+        let mut snapshot = false;
+        let mut bids = Levels::default();
+        let mut asks = Levels::default();
+        let mut checksum = None;
+        for data in &items[1..items.len() - 2] {
+            let data = data.as_object().ok_or_else(|| malformed(text, "Expect book data"))?;
+            for (key, value) in data {
+                snapshot |= key == "as" || key == "bs";
+                match key.as_str() {
+                    "a" | "as" => read_levels(text, value, &mut asks)?,
+                    "b" | "bs" => read_levels(text, value, &mut bids)?,
+                    "c" => {
+                        let value = value.as_str().and_then(|c| c.parse::<u32>().ok());
+                        checksum = Some(value.ok_or_else(|| malformed(text, "Bad checksum"))?);
+                    },
+                    _ => (),
+                }
+            }
+        }
+
+        let id = self.next_id();
+        if snapshot {
+            if let Some((price, volume)) = asks.decimals.or(bids.decimals) {
+                self.price_decimals.store(price, Ordering::SeqCst);
+                self.volume_decimals.store(volume, Ordering::SeqCst);
+            }
+            return Ok(Some(SourceMessage::Snapshot(BinanceSnapshot {
+                last_update_id: id,
+                bids: bids.rows,
+                asks: asks.rows,
+            })))
+        }
+
+        Ok(Some(SourceMessage::Update(BookUpdate {
+            event_time: asks.time.max(bids.time),
+            first_update_id: id,
+            last_update_id: id,
+            previous_update_id: id - 1,
+            bids: bids.rows,
+            asks: asks.rows,
+            checksum,
+        })))
+    }
+
+    fn snapshot_on_stream(&self) -> bool {
+        true
+    }
+
+    fn buffer_capacity(&self) -> usize {
+        BUFFER_CAPACITY
+    }
+
+    fn max_depth(&self) -> Option<usize> {
+        Some(self.depth)
+    }
+
+    fn verify(&self, book: &Shared, checksum: u32) -> Result<(), DepthError> {
+        let found = self.checksum(book);
+        if found == checksum {
+            Ok(())
+        } else {
+            Err(DepthError::ChecksumMismatch { expected: checksum, found })
+        }
+    }
+
+    fn match_snapshot(&self, update: &BookUpdate, last_update_id: i64) -> bool {
+        update.previous_update_id == last_update_id
+    }
+}
+
+#[test]
+fn kraken_fixture_checksums(){
+    use crate::source::{replay, Sequence};
+
+    let source = KrakenSource::new("ETH/XBT");
+    let (book, updates) = replay(&source, include_str!("../fixtures/kraken_book.jsonl"), |book| {
+        // Example of the Kraken checksum guide
+        assert_eq!(source.checksum(book), 974947235);
+    });
+
+    assert!(updates.iter().all(|(applied, _)| applied.sequence == Sequence::Apply));
+    assert_eq!(updates.len(), 3);
+    assert!(updates[0].1.is_ok());
+    // New best ask pushed 0.05050 out of the top 10
+    assert!(updates[1].1.is_ok());
+    assert_eq!(book.level_counts(), (10, 10));
+    assert!(matches!(updates[2].1, Err(DepthError::ChecksumMismatch { expected: 12345, .. })));
+}
+
+#[test]
+fn kraken_subscription_error(){
+    let text = r#"{"errorMessage":"Currency pair not supported XBT/XYZ","event":"subscriptionStatus","pair":"XBT/XYZ","status":"error","subscription":{"depth":10,"name":"book"}}"#;
+    crate::source::assert_venue_error(&KrakenSource::new("XBT/XYZ"), text);
+}
@@ -0,0 +1,118 @@
+use std::collections::VecDeque;
+
+/// Samples kept per tracker, percentiles are over this window
+pub const DEFAULT_LATENCY_WINDOW: usize = 1000;
+
+/// Percentiles of a latency window, in ms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyStats {
+    /// Samples recorded since start, not only in the window
+    pub count: u64,
+    pub min: i64,
+    pub p50: i64,
+    pub p90: i64,
+    pub p99: i64,
+    pub max: i64,
+}
+
+/// Rolling window of latency samples in ms
+pub struct LatencyTracker {
+    capacity: usize,
+    count: u64,
+    samples: VecDeque<i64>,
+}
+
+impl LatencyTracker {
+    pub fn new(capacity: usize) -> Self {
+        LatencyTracker {
+            capacity,
+            count: 0,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    pub fn record(&mut self, latency_ms: i64) {
+        if self.samples.len() == self.capacity {
+            let _ = self.samples.pop_front();
+        }
+        self.samples.push_back(latency_ms);
+        self.count += 1;
+    }
+
+    /// `None` until a sample is recorded
+    pub fn stats(&self) -> Option<LatencyStats> {
+        if self.samples.is_empty() {
+            return None
+        }
+
+        let mut sorted: Vec<i64> = self.samples.iter().copied().collect();
+        sorted.sort_unstable();
+        // Nearest rank
+        let percentile = |p: usize| sorted[((sorted.len() * p).div_ceil(100)).max(1) - 1];
+
+        Some(LatencyStats {
+            count: self.count,
+            min: sorted[0],
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// Latency of one stream, split by stage
+pub struct StreamLatency {
+    /// Exchange event time `E` to local receive
+    pub network: LatencyTracker,
+    /// Local receive to applied into the book
+    pub processing: LatencyTracker,
+}
+
+impl StreamLatency {
+    pub fn new(capacity: usize) -> Self {
+        StreamLatency {
+            network: LatencyTracker::new(capacity),
+            processing: LatencyTracker::new(capacity),
+        }
+    }
+
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            network: self.network.stats(),
+            processing: self.processing.stats(),
+        }
+    }
+}
+
+impl Default for StreamLatency {
+    fn default() -> Self {
+        Self::new(DEFAULT_LATENCY_WINDOW)
+    }
+}
+
+/// Latency percentiles of a stream, `None` for stages without samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyReport {
+    pub network: Option<LatencyStats>,
+    pub processing: Option<LatencyStats>,
+}
+
+#[test]
+fn latency_percentiles(){
+    let mut tracker = LatencyTracker::new(100);
+    assert!(tracker.stats().is_none());
+
+    // Oldest samples fall out of the window
+    for latency in [1000, 1000] {
+        tracker.record(latency);
+    }
+    for latency in 1..=100 {
+        tracker.record(latency);
+    }
+
+    let stats = tracker.stats().unwrap();
+    assert_eq!(stats.count, 102);
+    assert_eq!((stats.min, stats.max), (1, 100));
+    assert_eq!((stats.p50, stats.p90, stats.p99), (50, 90, 99));
+}
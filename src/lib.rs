@@ -0,0 +1,24 @@
+pub mod deep;
+pub mod book;
+pub mod connection;
+pub mod history;
+pub mod error;
+pub mod rest;
+pub mod limiter;
+pub mod logging;
+pub mod metrics;
+pub mod latency;
+pub mod clock;
+pub mod source;
+pub mod binance;
+pub mod kraken;
+pub mod okx;
+pub mod coinbase;
+pub mod bybit;
+pub mod consolidated;
+pub mod synthetic;
+pub mod ticker;
+pub mod trades;
+pub mod audit;
+pub mod divergence;
+pub mod alerts;
@@ -0,0 +1,129 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+/// Head room under the 6000 weight per minute Binance allows an IP
+pub const DEFAULT_WEIGHT_BUDGET: u32 = 5000;
+/// Head room under the 2400 weight per minute of the futures APIs,
+/// which are limited apart from spot
+pub const FUTURES_WEIGHT_BUDGET: u32 = 2000;
+const WINDOW: Duration = Duration::from_secs(60);
+
+static GLOBAL: OnceLock<WeightLimiter> = OnceLock::new();
+static USD_FUTURES: OnceLock<WeightLimiter> = OnceLock::new();
+static COIN_FUTURES: OnceLock<WeightLimiter> = OnceLock::new();
+
+/// Request weight budget per minute,
+/// requests over budget queue until the next window.
+pub struct WeightLimiter {
+    budget: u32,
+    /// Serve waiting requests in order
+    queue: tokio::sync::Mutex<()>,
+    window: Mutex<Window>,
+}
+
+struct Window {
+    start: Instant,
+    used: u32,
+}
+
+impl Window {
+    /// Start a new window once the current one is over
+    fn roll(&mut self, now: Instant) {
+        if now >= self.start + WINDOW {
+            self.start = now;
+            self.used = 0;
+        }
+    }
+}
+
+impl WeightLimiter {
+    pub fn new(budget: u32) -> Self {
+        WeightLimiter {
+            budget,
+            queue: tokio::sync::Mutex::new(()),
+            window: Mutex::new(Window { start: Instant::now(), used: 0 }),
+        }
+    }
+
+    /// Limiter shared by every order book of the process
+    pub fn global() -> &'static WeightLimiter {
+        GLOBAL.get_or_init(|| WeightLimiter::new(DEFAULT_WEIGHT_BUDGET))
+    }
+
+    /// Limiter of the USD-M futures API (fapi) shared by the process
+    pub fn usd_futures() -> &'static WeightLimiter {
+        USD_FUTURES.get_or_init(|| WeightLimiter::new(FUTURES_WEIGHT_BUDGET))
+    }
+
+    /// Limiter of the COIN-M futures API (dapi) shared by the process
+    pub fn coin_futures() -> &'static WeightLimiter {
+        COIN_FUTURES.get_or_init(|| WeightLimiter::new(FUTURES_WEIGHT_BUDGET))
+    }
+
+    /// Set the budget of the global limiter,
+    /// return false if it is already in use
+    pub fn init_global(budget: u32) -> bool {
+        GLOBAL.set(WeightLimiter::new(budget)).is_ok()
+    }
+
+    pub fn budget(&self) -> u32 {
+        self.budget
+    }
+
+    /// Weight used in the current window
+    pub fn used(&self) -> u32 {
+        let mut window = self.window.lock().unwrap();
+        window.roll(Instant::now());
+        window.used
+    }
+
+    /// Used weight over budget, can exceed 1.0 if Binance reports more than we spent
+    pub fn utilization(&self) -> f64 {
+        self.used() as f64 / self.budget as f64
+    }
+
+    /// Wait until `weight` fits in the budget and spend it.
+    /// A request heavier than the whole budget goes alone in a fresh window.
+    pub async fn acquire(&self, weight: u32) {
+        let _turn = self.queue.lock().await;
+        loop {
+            let next_window = {
+                let mut window = self.window.lock().unwrap();
+                window.roll(Instant::now());
+                if window.used == 0 || window.used + weight <= self.budget {
+                    window.used += weight;
+                    return
+                }
+                window.start + WINDOW
+            };
+            sleep_until(next_window).await;
+        }
+    }
+
+    /// Align with the weight Binance reports (`X-MBX-USED-WEIGHT-1M`),
+    /// which also counts requests made outside this limiter
+    pub fn update_used(&self, used: u32) {
+        let mut window = self.window.lock().unwrap();
+        window.roll(Instant::now());
+        window.used = window.used.max(used);
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn limiter_queues_over_budget(){
+    let limiter = WeightLimiter::new(100);
+    let start = Instant::now();
+
+    limiter.acquire(50).await;
+    limiter.acquire(50).await;
+    assert_eq!(start.elapsed(), Duration::ZERO);
+    assert_eq!(limiter.utilization(), 1.0);
+
+    limiter.acquire(50).await;
+    assert_eq!(start.elapsed(), WINDOW);
+    assert_eq!(limiter.used(), 50);
+
+    limiter.update_used(90);
+    assert_eq!(limiter.used(), 90);
+}
@@ -0,0 +1,14 @@
+use tracing_subscriber::EnvFilter;
+
+/// Install the global `tracing` subscriber.
+/// Levels come from `RUST_LOG` (default "info"),
+/// `LOG_FORMAT=json` switches to one JSON object per line.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+
+    match std::env::var("LOG_FORMAT") {
+        Ok(format) if format.eq_ignore_ascii_case("json") => builder.json().init(),
+        _ => builder.init(),
+    }
+}
@@ -1,63 +1,215 @@
-pub mod deep;
-mod connection;
-
-use connection::{BinanceSpotOrderBook};
+use depth_compare::connection::{BinanceSpotOrderBook, Market, DEFAULT_SYMBOL};
+use depth_compare::metrics::{self, Metrics};
+use depth_compare::clock::{now_ms, ServerClock, DEFAULT_SYNC_INTERVAL};
+use depth_compare::okx::OkxSource;
+use depth_compare::trades::TradeStream;
+use depth_compare::divergence::{Divergence, DivergenceStats};
+use depth_compare::alerts::{sink_from_spec, Alerter};
+use depth_compare::coinbase::CoinbaseSource;
+use depth_compare::bybit::{BybitCategory, BybitSource, DEFAULT_DEPTH as BYBIT_DEPTH};
 // use deep::Event;
 // use tokio_tungstenite::connect_async;
 // use url::Url;
 // use tokio::net::TcpStream;
 use tokio::time::{sleep, Duration};
 // use futures_util::StreamExt;
-use anyhow::Result;
-use tokio::sync::Mutex;
-use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use tracing::{error, info, info_span, warn, Instrument};
 // use tokio::spawn;
 
+const METRICS_ADDR: &str = "127.0.0.1:9898";
+/// Versions of the diff book kept for bookTicker checks, about a second of updates
+const TICKER_HISTORY: usize = 16;
+/// Versions kept for REST audits, covering the events buffered around the snapshot
+const AUDIT_HISTORY: usize = 100;
+const OKX_SYMBOL: &str = "BTC-USDT";
+const COINBASE_SYMBOL: &str = "BTC-USD";
+const BYBIT_SYMBOL: &str = "BTCUSDT";
+/// Deepest orderbook topic on both bybit spot and linear
+const BYBIT_FULL_DEPTH: usize = 200;
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    depth_compare::logging::init();
+
+    // Prometheus endpoint, `METRICS_ADDR` overrides the default address
+    let metrics_addr = std::env::var("METRICS_ADDR").unwrap_or_else(|_| METRICS_ADDR.to_string());
+    let metrics_addr = metrics_addr.parse()?;
+    tokio::spawn(async move {
+        if let Err(e) = metrics::serve(metrics_addr).await {
+            error!(error = %e, "fail to serve metrics");
+        }
+    });
 
-    let order_book_depth = BinanceSpotOrderBook::new();
-    let order_book_level_depth = BinanceSpotOrderBook::new();
+    // Correct local timestamps to the exchange clock
+    tokio::spawn(ServerClock::global().run(DEFAULT_SYNC_INTERVAL));
 
-    // Start depth order book
-    match order_book_depth.depth(){
-        Ok(_) => (),
-        Err(e) => println!("{}",e),
+    // `VENUE` (binance, okx, coinbase, bybit), `MARKET` (spot, usdm, coinm on binance,
+    // spot, usdm on bybit)
+    // and `SYMBOL` select the order books to compare
+    let venue = std::env::var("VENUE").unwrap_or_else(|_| "binance".to_string());
+    let market: Market = std::env::var("MARKET").unwrap_or_else(|_| "spot".to_string()).parse()?;
+    let default_symbol = match venue.as_str() {
+        "okx" => OKX_SYMBOL,
+        "coinbase" => COINBASE_SYMBOL,
+        "bybit" => BYBIT_SYMBOL,
+        _ => DEFAULT_SYMBOL,
+    };
+    let symbol = std::env::var("SYMBOL").unwrap_or_else(|_| default_symbol.to_string());
+    // `BOOK_TICKER=1` checks the diff book against bookTicker on binance,
+    // the history lets tickers be checked after the next batch of updates
+    let book_ticker = std::env::var("BOOK_TICKER").is_ok_and(|value| value == "1");
+    // `AUDIT_INTERVAL` (seconds) audits both books against REST snapshots on binance
+    let audit_interval = match std::env::var("AUDIT_INTERVAL") {
+        Ok(seconds) => Some(Duration::from_secs(seconds.parse()?)),
+        Err(_) => None,
+    };
+    let history = match (audit_interval, book_ticker) {
+        (Some(_), _) => AUDIT_HISTORY,
+        (None, true) => TICKER_HISTORY,
+        (None, false) => 0,
     };
+    let level_history = if audit_interval.is_some() { AUDIT_HISTORY } else { 0 };
+    let order_book_depth = BinanceSpotOrderBook::for_market(market, &symbol, history);
+    let order_book_level_depth = BinanceSpotOrderBook::for_market(market, &symbol, level_history);
 
-    // Start depth level order book
-    order_book_level_depth.level_depth();
+    match venue.as_str() {
+        "binance" => {
+            // Start depth order book
+            match order_book_depth.depth(){
+                Ok(_) => (),
+                Err(e) => error!(error = %e, "fail to start depth order book"),
+            };
+
+            // Start depth level order book
+            order_book_level_depth.level_depth();
+
+            if book_ticker {
+                order_book_depth.book_ticker();
+            }
+
+            if let Some(interval) = audit_interval {
+                order_book_depth.audit(&order_book_level_depth, interval);
+            }
+
+            // `TRADES` (trade, aggTrade) splits level decreases into fills and cancels
+            match std::env::var("TRADES").as_deref() {
+                Ok("trade") => order_book_depth.trades(TradeStream::Trade),
+                Ok("aggTrade") => order_book_depth.trades(TradeStream::AggTrade),
+                Ok(other) => return Err(anyhow!("Unknown trade stream {:?}, expect trade or aggTrade", other)),
+                Err(_) => (),
+            }
+        },
+        // Full book against the top 5 levels
+        "okx" => {
+            order_book_depth.sync_source(OkxSource::books(&symbol));
+            order_book_level_depth.sync_source(OkxSource::books5(&symbol));
+        },
+        // Level2 book against the best bid and ask of the ticker channel
+        "coinbase" => {
+            order_book_depth.sync_source(CoinbaseSource::new(&symbol));
+            order_book_level_depth.sync_source(CoinbaseSource::ticker(&symbol));
+        },
+        // Deep book against the depth 50 one
+        "bybit" => {
+            let category = match market {
+                Market::Spot => BybitCategory::Spot,
+                Market::UsdFutures => BybitCategory::Linear,
+                Market::CoinFutures => return Err(anyhow!("No {} market on bybit", market)),
+            };
+            order_book_depth.sync_source(BybitSource::new(category, &symbol, BYBIT_FULL_DEPTH));
+            order_book_level_depth.sync_source(BybitSource::new(category, &symbol, BYBIT_DEPTH));
+        },
+        _ => return Err(anyhow!("Unknown venue {:?}, expect binance, okx, coinbase or bybit", venue)),
+    }
 
-    loop{
-        println!();
-        println!();
-        sleep(Duration::from_secs(1)).await;
-        let depth = order_book_depth.get_snapshot().await;
+    let symbol = order_book_depth.symbol();
+    let differing_bids = Metrics::global().differing_levels.with_label_values(&[symbol, "bids"]);
+    let differing_asks = Metrics::global().differing_levels.with_label_values(&[symbol, "asks"]);
+    let span = info_span!("compare", %venue, %market, symbol = %symbol);
+    let mut divergence_stats = DivergenceStats::default();
 
-        let depth_level = order_book_level_depth.get_snapshot().await;
-        if depth_level.is_none() || depth.is_none(){
-            println!("depth_level {}, depth {}", depth_level.is_none(), depth.is_none());
-            continue
+    // `ALERT_RULES` (e.g. "levels:5:3,best_bid:2000") alert on the comparisons,
+    // to the `ALERT_SINKS` (stderr, file:<path>, webhook:<url>, unix:<path>)
+    let mut alerter = Alerter::new(symbol);
+    if let Ok(rules) = std::env::var("ALERT_RULES") {
+        for rule in rules.split(',') {
+            alerter.add_rule(rule.parse()?);
         }
-        //
-        let depth = depth.unwrap();
-        let depth_level = depth_level.unwrap();
-        let depth_time = depth.time_stamp;
-        let depth_level_time = depth_level.time_stamp;
-        let contains = depth.if_contains(&depth_level);
+        let sinks = std::env::var("ALERT_SINKS").unwrap_or_else(|_| "stderr".to_string());
+        for sink in sinks.split(',') {
+            alerter.add_sink(sink_from_spec(sink)?);
+        }
+    }
+    async {
+        loop{
+            // Ctrl-C stops comparing and prints the divergence summary
+            tokio::select! {
+                _ = sleep(Duration::from_secs(1)) => (),
+                _ = tokio::signal::ctrl_c() => break,
+            }
+            let depth = order_book_depth.get_snapshot().await;
+
+            let depth_level = order_book_level_depth.get_snapshot().await;
+            if depth_level.is_none() || depth.is_none(){
+                info!(depth_level_ready = depth_level.is_some(), depth_ready = depth.is_some(), "waiting for order books");
+                continue
+            }
+            //
+            let depth = depth.unwrap();
+            let depth_level = depth_level.unwrap();
+            let depth_time = depth.time_stamp;
+            let depth_level_time = depth_level.time_stamp;
+            let contains = depth.if_contains(&depth_level);
+
+            info!(
+                depth_time,
+                depth_level_time,
+                depth_update_id = depth.last_update_id,
+                depth_level_update_id = depth_level.last_update_id,
+                contains,
+                clock_offset_ms = ServerClock::global().offset().map(|offset| offset.offset_ms),
+                "compared order books"
+            );
+
+            let latency = order_book_depth.depth_latency();
+            if let (Some(network), Some(processing)) = (latency.network, latency.processing) {
+                info!(
+                    network_p50_ms = network.p50,
+                    network_p99_ms = network.p99,
+                    processing_p50_ms = processing.p50,
+                    processing_p99_ms = processing.p99,
+                    "depth latency"
+                );
+            }
 
-        println!("{} {}, contains? {}", depth_time, depth_level_time, contains);
+            if let Some((totals, off_book)) = order_book_depth.with_trades(|trades| (trades.totals(), trades.off_book_count())) {
+                info!(
+                    filled = totals.filled,
+                    cancelled = totals.cancelled,
+                    fills = totals.fills,
+                    cancels = totals.cancels,
+                    off_book_trades = off_book,
+                    "trade attribution"
+                );
+            }
+
+            let now = now_ms();
+            divergence_stats.record(Divergence::between(&depth, &depth_level, now));
+            alerter.check(&depth, &depth_level, now);
 
-        if !contains {
             let (different_bids, different_asks ) = depth.find_different(&depth_level);
-            println!("bids different {}", different_bids.len());
-            // println!("{:?}", different_bids);
-            println!("asks different {}", different_asks.len());
-            // println!("{:?}", different_asks);
-        }
+            differing_bids.set(different_bids.len() as i64);
+            differing_asks.set(different_asks.len() as i64);
+            if !contains {
+                warn!(bids_different = different_bids.len(), asks_different = different_asks.len(), "order books differ");
+                // println!("{:?}", different_bids);
+                // println!("{:?}", different_asks);
+            }
 
-    }
+        }
+    }.instrument(span).await;
 
+    println!("{}", divergence_stats);
     Ok(())
-
 }
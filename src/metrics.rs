@@ -0,0 +1,232 @@
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use prometheus::{Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+use crate::deep::Shared;
+
+static GLOBAL: OnceLock<Metrics> = OnceLock::new();
+
+/// Per symbol health of the order books, labelled by `symbol` and `stream`
+pub struct Metrics {
+    registry: Registry,
+    /// 1 while the book is in sync, 0 while waiting for a snapshot
+    pub sync_state: IntGaugeVec,
+    pub update_id: IntGaugeVec,
+    pub events_applied: IntCounterVec,
+    pub gaps: IntCounterVec,
+    pub checksum_mismatches: IntCounterVec,
+    pub resyncs: IntCounterVec,
+    pub reconnects: IntCounterVec,
+    /// Local receive time minus event time `E`, in ms
+    pub message_lag_ms: IntGaugeVec,
+    /// Per message latency in ms, also labelled by `stage`:
+    /// "network" (event time to receive) or "processing" (receive to applied)
+    pub latency_ms: HistogramVec,
+    /// Also labelled by `side`
+    pub book_levels: IntGaugeVec,
+    /// Levels of the depth20 book missing from the diff book,
+    /// labelled by `symbol` and `side`
+    pub differing_levels: IntGaugeVec,
+    /// bookTicker messages checked against the diff book, also labelled
+    /// by `result`: "match", "mismatch" or "unverified"
+    pub top_of_book_checks: IntCounterVec,
+    /// Levels differing from the last REST audit, labelled by `symbol` and `book`
+    pub audit_differing_levels: IntGaugeVec,
+    /// Exchange clock minus local clock, in ms
+    pub clock_offset_ms: IntGauge,
+    pub clock_uncertainty_ms: IntGauge,
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    let gauge = IntGaugeVec::new(Opts::new(name, help), labels).expect("Bad metric");
+    registry.register(Box::new(gauge.clone())).expect("Metric registered twice");
+    gauge
+}
+
+fn histogram(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> HistogramVec {
+    let buckets = vec![1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0];
+    let histogram = HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets), labels).expect("Bad metric");
+    registry.register(Box::new(histogram.clone())).expect("Metric registered twice");
+    histogram
+}
+
+fn single_gauge(registry: &Registry, name: &str, help: &str) -> IntGauge {
+    let gauge = IntGauge::new(name, help).expect("Bad metric");
+    registry.register(Box::new(gauge.clone())).expect("Metric registered twice");
+    gauge
+}
+
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).expect("Bad metric");
+    registry.register(Box::new(counter.clone())).expect("Metric registered twice");
+    counter
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let stream = &["symbol", "stream"];
+        Metrics {
+            sync_state: gauge(&registry, "depth_sync_state", "1 if the book is in sync", stream),
+            update_id: gauge(&registry, "depth_update_id", "Last applied update id", stream),
+            events_applied: counter(&registry, "depth_events_applied_total", "Events applied to the book", stream),
+            gaps: counter(&registry, "depth_gaps_total", "Sequence gaps detected", stream),
+            checksum_mismatches: counter(&registry, "depth_checksum_mismatches_total", "Books not matching the venue checksum", stream),
+            resyncs: counter(&registry, "depth_resyncs_total", "Books rebuilt from a new snapshot", stream),
+            reconnects: counter(&registry, "depth_websocket_reconnects_total", "Websocket reconnections", stream),
+            message_lag_ms: gauge(&registry, "depth_message_lag_ms", "Receive time minus event time", stream),
+            latency_ms: histogram(&registry, "depth_latency_ms", "Message latency by stage", &["symbol", "stream", "stage"]),
+            book_levels: gauge(&registry, "depth_book_levels", "Price levels in the book", &["symbol", "stream", "side"]),
+            differing_levels: gauge(
+                &registry,
+                "depth_differing_levels",
+                "depth20 levels not found in the diff book",
+                &["symbol", "side"],
+            ),
+            top_of_book_checks: counter(
+                &registry,
+                "depth_top_of_book_checks_total",
+                "bookTicker messages checked against the book",
+                &["symbol", "stream", "result"],
+            ),
+            audit_differing_levels: gauge(
+                &registry,
+                "depth_audit_differing_levels",
+                "Levels differing from a REST snapshot",
+                &["symbol", "book"],
+            ),
+            clock_offset_ms: single_gauge(&registry, "clock_offset_ms", "Exchange clock minus local clock"),
+            clock_uncertainty_ms: single_gauge(&registry, "clock_uncertainty_ms", "Uncertainty of the clock offset"),
+            registry,
+        }
+    }
+
+    /// Metrics shared by every order book of the process
+    pub fn global() -> &'static Metrics {
+        GLOBAL.get_or_init(Metrics::new)
+    }
+
+    /// Metrics of `stream` of `symbol`
+    pub fn stream(&self, symbol: &str, stream: &str) -> StreamMetrics {
+        let labels = &[symbol, stream];
+        StreamMetrics {
+            sync_state: self.sync_state.with_label_values(labels),
+            update_id: self.update_id.with_label_values(labels),
+            events_applied: self.events_applied.with_label_values(labels),
+            gaps: self.gaps.with_label_values(labels),
+            checksum_mismatches: self.checksum_mismatches.with_label_values(labels),
+            resyncs: self.resyncs.with_label_values(labels),
+            reconnects: self.reconnects.with_label_values(labels),
+            message_lag_ms: self.message_lag_ms.with_label_values(labels),
+            network_latency_ms: self.latency_ms.with_label_values(&[symbol, stream, "network"]),
+            processing_latency_ms: self.latency_ms.with_label_values(&[symbol, stream, "processing"]),
+            bid_levels: self.book_levels.with_label_values(&[symbol, stream, "bids"]),
+            ask_levels: self.book_levels.with_label_values(&[symbol, stream, "asks"]),
+            top_matches: self.top_of_book_checks.with_label_values(&[symbol, stream, "match"]),
+            top_mismatches: self.top_of_book_checks.with_label_values(&[symbol, stream, "mismatch"]),
+            top_unverified: self.top_of_book_checks.with_label_values(&[symbol, stream, "unverified"]),
+        }
+    }
+
+    /// Text exposition format
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Fail to encode metrics");
+        String::from_utf8(buffer).expect("Metrics are not utf8")
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Metrics of one stream with labels already resolved
+#[derive(Clone)]
+pub struct StreamMetrics {
+    pub sync_state: IntGauge,
+    pub update_id: IntGauge,
+    pub events_applied: IntCounter,
+    pub gaps: IntCounter,
+    pub checksum_mismatches: IntCounter,
+    pub resyncs: IntCounter,
+    pub reconnects: IntCounter,
+    pub message_lag_ms: IntGauge,
+    pub network_latency_ms: Histogram,
+    pub processing_latency_ms: Histogram,
+    pub bid_levels: IntGauge,
+    pub ask_levels: IntGauge,
+    pub top_matches: IntCounter,
+    pub top_mismatches: IntCounter,
+    pub top_unverified: IntCounter,
+}
+
+impl StreamMetrics {
+    /// Record update id and level counts of `book`
+    pub fn observe_book(&self, book: &Shared) {
+        let (bids, asks) = book.level_counts();
+        self.update_id.set(book.id());
+        self.bid_levels.set(bids as i64);
+        self.ask_levels.set(asks as i64);
+    }
+}
+
+/// Serve the global metrics on `http://<addr>/metrics`
+pub async fn serve(addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(%addr, "serving metrics");
+    serve_listener(listener, Metrics::global()).await
+}
+
+async fn serve_listener(listener: TcpListener, metrics: &'static Metrics) -> std::io::Result<()> {
+    loop {
+        let (mut socket, peer) = listener.accept().await?;
+        tokio::spawn(async move {
+            // Only the request line matters
+            let mut buffer = [0u8; 1024];
+            let len = match socket.read(&mut buffer).await {
+                Ok(len) => len,
+                Err(e) => {
+                    warn!(%peer, error = %e, "fail to read metrics request");
+                    return
+                },
+            };
+
+            let request = String::from_utf8_lossy(&buffer[..len]);
+            let response = if request.starts_with("GET /metrics ") {
+                let body = metrics.encode();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+            };
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[tokio::test]
+async fn metrics_endpoint(){
+    let metrics: &'static Metrics = Box::leak(Box::new(Metrics::new()));
+    metrics.update_id.with_label_values(&["bnbbtc", "depth@100ms"]).set(42);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve_listener(listener, metrics));
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.contains(r#"depth_update_id{stream="depth@100ms",symbol="bnbbtc"} 42"#));
+}
@@ -0,0 +1,194 @@
+use serde::Deserialize;
+use serde::de::IgnoredAny;
+use serde_json::json;
+use url::Url;
+use crate::deep::{BinanceSnapshot, Shared};
+use crate::error::DepthError;
+use crate::source::{malformed, rows, BookUpdate, OrderBookSource, SourceMessage};
+
+const STREAM: &str = "wss://ws.okx.com:8443/ws/v5/public";
+/// Levels per side covered by the checksum
+const CHECKSUM_DEPTH: usize = 25;
+/// `books` pushes the changes of each 100ms, 10s of them
+const BUFFER_CAPACITY: usize = 100;
+
+/// Public order book channel of OKX
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OkxChannel {
+    /// 400 levels, snapshot then updates linked by `seqId`/`prevSeqId`
+    Books,
+    /// Top 5 levels, every message is a snapshot
+    Books5,
+}
+
+impl OkxChannel {
+    fn name(self) -> &'static str {
+        match self {
+            OkxChannel::Books => "books",
+            OkxChannel::Books5 => "books5",
+        }
+    }
+}
+
+/// Order book of an OKX instrument (e.g. "BTC-USDT"),
+/// `books` is checked against the checksum OKX sends with every message
+pub struct OkxSource {
+    inst_id: String,
+    channel: OkxChannel,
+}
+
+impl OkxSource {
+    pub fn books(inst_id: &str) -> Self {
+        OkxSource { inst_id: inst_id.to_uppercase(), channel: OkxChannel::Books }
+    }
+
+    /// Partial book to compare `books` against, as depth20 is for Binance
+    pub fn books5(inst_id: &str) -> Self {
+        OkxSource { inst_id: inst_id.to_uppercase(), channel: OkxChannel::Books5 }
+    }
+}
+
+#[derive(Deserialize)]
+struct OkxMessage {
+    event: Option<String>,
+    msg: Option<String>,
+    action: Option<String>,
+    #[serde(default)]
+    data: Vec<OkxBook>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OkxBook {
+    asks: Vec<OkxLevel>,
+    bids: Vec<OkxLevel>,
+    ts: String,
+    checksum: Option<i32>,
+    prev_seq_id: Option<i64>,
+    seq_id: Option<i64>,
+}
+
+/// `[price, size, deprecated, orders]`
+type OkxLevel = (String, String, IgnoredAny, IgnoredAny);
+
+/// CRC32 of the top 25 levels interleaved as `bid:size:ask:size:...`,
+/// carrying on with the deeper side once the other runs out.
+/// OKX sends it as an `i32`, compare with `checksum as u32`.
+/// Numbers are formatted as OKX sends them, without trailing zeros.
+pub fn checksum(book: &Shared) -> u32 {
+    let bids = book.top_bids(CHECKSUM_DEPTH);
+    let asks = book.top_asks(CHECKSUM_DEPTH);
+    let mut fields = Vec::with_capacity(4 * CHECKSUM_DEPTH);
+    for i in 0..bids.len().max(asks.len()) {
+        for row in [bids.get(i), asks.get(i)].into_iter().flatten() {
+            fields.push(row.price.to_string());
+            fields.push(row.amount.to_string());
+        }
+    }
+    crc32fast::hash(fields.join(":").as_bytes())
+}
+
+impl OrderBookSource for OkxSource {
+    fn stream(&self) -> String {
+        format!("okx/{}", self.channel.name())
+    }
+
+    fn stream_url(&self) -> Url {
+        Url::parse(STREAM).expect("Bad URL")
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        let subscribe = json!({
+            "op": "subscribe",
+            "args": [{"channel": self.channel.name(), "instId": self.inst_id}],
+        });
+        vec![subscribe.to_string()]
+    }
+
+    fn parse(&self, text: &str) -> Result<Option<SourceMessage>, DepthError> {
+        // Keep alive answer to a "ping"
+        if text == "pong" {
+            return Ok(None)
+        }
+        let message: OkxMessage = serde_json::from_str(text).map_err(|e| DepthError::parse(text, e))?;
+        match message.event.as_deref() {
+            Some("error") => return Err(DepthError::Venue(message.msg.unwrap_or_else(|| text.to_string()))),
+            // Subscription results and notices
+            Some(_) => return Ok(None),
+            None => (),
+        }
+
+        let book = match message.data.into_iter().next() {
+            Some(book) => book,
+            None => return Ok(None),
+        };
+        let seq_id = book.seq_id.unwrap_or_default();
+        let bids = rows(text, book.bids.into_iter().map(|(price, size, ..)| (price, size)))?;
+        let asks = rows(text, book.asks.into_iter().map(|(price, size, ..)| (price, size)))?;
+
+        let snapshot = self.channel == OkxChannel::Books5 || message.action.as_deref() == Some("snapshot");
+        if snapshot {
+            return Ok(Some(SourceMessage::Snapshot(BinanceSnapshot { last_update_id: seq_id, bids, asks })))
+        }
+
+        let prev_seq_id = book.prev_seq_id.ok_or_else(|| malformed(text, "Missing prevSeqId"))?;
+        Ok(Some(SourceMessage::Update(BookUpdate {
+            event_time: book.ts.parse().map_err(|_| malformed(text, "Bad ts"))?,
+            first_update_id: seq_id,
+            last_update_id: seq_id,
+            previous_update_id: prev_seq_id,
+            bids,
+            asks,
+            checksum: book.checksum.map(|checksum| checksum as u32),
+        })))
+    }
+
+    fn snapshot_on_stream(&self) -> bool {
+        true
+    }
+
+    fn buffer_capacity(&self) -> usize {
+        BUFFER_CAPACITY
+    }
+
+    fn verify(&self, book: &Shared, checksum: u32) -> Result<(), DepthError> {
+        let found = self::checksum(book);
+        if found == checksum {
+            Ok(())
+        } else {
+            Err(DepthError::ChecksumMismatch { expected: checksum, found })
+        }
+    }
+
+    /// `prevSeqId` is the `seqId` of the previous message,
+    /// they are equal on updates without change
+    fn match_snapshot(&self, update: &BookUpdate, last_update_id: i64) -> bool {
+        update.previous_update_id == last_update_id
+    }
+}
+
+#[test]
+fn okx_fixture_checksums(){
+    use crate::deep::DepthRow;
+    use crate::source::{replay, Sequence};
+
+    let source = OkxSource::books("BTC-USDT");
+    let (book, updates) = replay(&source, include_str!("../fixtures/okx_books.jsonl"), |book| {
+        assert_eq!(checksum(book) as i32, -1117324716);
+    });
+
+    assert!(updates.iter().all(|(applied, _)| applied.sequence == Sequence::Apply));
+    assert_eq!(updates.len(), 3);
+    assert!(updates[0].1.is_ok() && updates[1].1.is_ok());
+    assert_eq!(book.top_bids(1), vec![DepthRow { price: 3366.2, amount: 1.25 }]);
+    assert!(matches!(updates[2].1, Err(DepthError::ChecksumMismatch { expected, .. }) if expected as i32 == -1234));
+}
+
+#[test]
+fn okx_books5_is_snapshot(){
+    let text = r#"{"arg":{"channel":"books5","instId":"BTC-USDT"},"data":[{"asks":[["8446","95","0","3"]],"bids":[["8445","70","0","2"]],"instId":"BTC-USDT","ts":"1597026383085","seqId":123456}]}"#;
+    match OkxSource::books5("btc-usdt").parse(text).unwrap() {
+        Some(SourceMessage::Snapshot(snapshot)) => assert_eq!(snapshot.last_update_id, 123456),
+        other => panic!("Expect a snapshot, found {:?}", other),
+    }
+}
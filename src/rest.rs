@@ -0,0 +1,189 @@
+use std::time::Duration;
+use serde::Deserialize;
+use tokio::time::{sleep_until, Instant};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use crate::deep::BinanceSnapshot;
+use crate::error::DepthError;
+use crate::limiter::WeightLimiter;
+
+/// Weight used by this IP in the current minute
+const USED_WEIGHT: &str = "x-mbx-used-weight-1m";
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Error body Binance answers with, e.g. `{"code":-1121,"msg":"Invalid symbol."}`
+#[derive(Deserialize, Debug)]
+pub struct BinanceApiError {
+    pub code: i64,
+    pub msg: String,
+}
+
+#[derive(Deserialize)]
+struct ExchangeInfo {
+    symbols: Vec<ContractInfo>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ContractInfo {
+    symbol: String,
+    contract_size: f64,
+}
+
+/// Contract size of a COIN-M `symbol` (e.g. "BTCUSD_PERP") from the dapi `exchangeInfo` at `url`
+pub async fn fetch_contract_size(url: &str, symbol: &str) -> Result<f64, DepthError> {
+    WeightLimiter::coin_futures().acquire(1).await;
+
+    let response = reqwest::get(url).await?;
+    let status = response.status().as_u16();
+    let body = response.text().await?;
+    if !(200..300).contains(&status) {
+        return Err(status_error(status, None, &body))
+    }
+    contract_size_of(&body, symbol)
+}
+
+fn contract_size_of(body: &str, symbol: &str) -> Result<f64, DepthError> {
+    let info: ExchangeInfo = serde_json::from_str(body).map_err(|e| DepthError::parse(body, e))?;
+    info.symbols
+        .iter()
+        .find(|contract| contract.symbol.eq_ignore_ascii_case(symbol))
+        .map(|contract| contract.contract_size)
+        .ok_or_else(|| DepthError::UnknownSymbol(symbol.to_string()))
+}
+
+/// Fetch REST snapshots, backing off after failures
+/// and honouring the rate limit headers of Binance.
+/// Every request goes through a process wide `WeightLimiter`.
+pub struct SnapshotFetcher {
+    client: reqwest::Client,
+    limiter: &'static WeightLimiter,
+    url: String,
+    /// Request weight of `url`
+    weight: u32,
+    used_weight: Option<u32>,
+    failures: u32,
+    /// No request is sent before this
+    retry_at: Option<Instant>,
+}
+
+impl SnapshotFetcher {
+    /// Fetcher of a spot snapshot, limited by the global `WeightLimiter`
+    pub fn new(url: &str, weight: u32) -> Self {
+        Self::with_limiter(url, weight, WeightLimiter::global())
+    }
+
+    /// Fetcher of an API with its own limit, e.g. futures
+    pub fn with_limiter(url: &str, weight: u32, limiter: &'static WeightLimiter) -> Self {
+        SnapshotFetcher {
+            client: reqwest::Client::new(),
+            limiter,
+            url: url.to_string(),
+            weight,
+            used_weight: None,
+            failures: 0,
+            retry_at: None,
+        }
+    }
+
+    /// Request weight used in the current minute, as last reported by Binance
+    pub fn used_weight(&self) -> Option<u32> {
+        self.used_weight
+    }
+
+    /// Wait for any pending back off, then fetch a snapshot
+    pub async fn fetch(&mut self) -> Result<BinanceSnapshot, DepthError> {
+        if let Some(retry_at) = self.retry_at.take() {
+            sleep_until(retry_at).await;
+        }
+
+        match self.request().await {
+            Ok(snapshot) => {
+                self.failures = 0;
+                Ok(snapshot)
+            },
+            Err(e) => {
+                self.failures += 1;
+                let delay = match &e {
+                    DepthError::RateLimited { retry_after: Some(retry_after), .. } => *retry_after,
+                    DepthError::RateLimited { .. } => MAX_BACKOFF,
+                    _ => backoff(self.failures),
+                };
+                self.retry_at = Some(Instant::now() + delay);
+                Err(e)
+            },
+        }
+    }
+
+    async fn request(&mut self) -> Result<BinanceSnapshot, DepthError> {
+        let limiter = self.limiter;
+        limiter.acquire(self.weight).await;
+
+        let response = self.client.get(&self.url).send().await?;
+        let status = response.status().as_u16();
+        if let Some(weight) = header_value(response.headers(), USED_WEIGHT) {
+            self.used_weight = Some(weight as u32);
+            limiter.update_used(weight as u32);
+        }
+        let retry_after = header_value(response.headers(), RETRY_AFTER.as_str())
+            .map(Duration::from_secs);
+
+        let body = response.text().await?;
+        if !(200..300).contains(&status) {
+            return Err(status_error(status, retry_after, &body))
+        }
+
+        serde_json::from_str(&body).map_err(|e| DepthError::parse(&body, e))
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Turn a non success response into the matching `DepthError`
+pub(crate) fn status_error(status: u16, retry_after: Option<Duration>, body: &str) -> DepthError {
+    // 429: too many requests, 418: IP banned for ignoring 429s
+    if status == 429 || status == 418 {
+        return DepthError::RateLimited { status, retry_after }
+    }
+
+    match serde_json::from_str::<BinanceApiError>(body) {
+        Ok(error) => DepthError::Api { status, code: error.code, msg: error.msg },
+        Err(_) => DepthError::HttpStatus { status, body: body.to_string() },
+    }
+}
+
+/// Exponential back off, 1s after the first failure up to `MAX_BACKOFF`
+fn backoff(failures: u32) -> Duration {
+    let secs = 1u64 << failures.saturating_sub(1).min(6);
+    Duration::from_secs(secs).min(MAX_BACKOFF)
+}
+
+#[test]
+fn status_error_kinds(){
+    let error = status_error(400, None, r#"{"code":-1121,"msg":"Invalid symbol."}"#);
+    assert!(matches!(error, DepthError::Api { status: 400, code: -1121, .. }));
+
+    let error = status_error(429, Some(Duration::from_secs(7)), r#"{"code":-1003,"msg":"Too many requests."}"#);
+    assert!(matches!(error, DepthError::RateLimited { status: 429, retry_after: Some(d) } if d.as_secs() == 7));
+
+    let error = status_error(502, None, "<html>Bad Gateway</html>");
+    assert!(matches!(error, DepthError::HttpStatus { status: 502, .. }));
+}
+
+#[test]
+fn contract_size_from_exchange_info(){
+    let body = r#"{"timezone":"UTC","symbols":[
+        {"symbol":"BTCUSD_PERP","pair":"BTCUSD","contractType":"PERPETUAL","contractSize":100},
+        {"symbol":"ETHUSD_PERP","pair":"ETHUSD","contractType":"PERPETUAL","contractSize":10}
+    ]}"#;
+    assert_eq!(contract_size_of(body, "ethusd_perp").unwrap(), 10.0);
+    assert!(matches!(contract_size_of(body, "BTCUSD_250627"), Err(DepthError::UnknownSymbol(_))));
+}
+
+#[test]
+fn backoff_grows_to_max(){
+    assert_eq!(backoff(1), Duration::from_secs(1));
+    assert_eq!(backoff(3), Duration::from_secs(4));
+    assert_eq!(backoff(20), MAX_BACKOFF);
+}
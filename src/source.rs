@@ -0,0 +1,253 @@
+use std::future::Future;
+use url::Url;
+use crate::deep::{BinanceSnapshot, DepthRow, Shared};
+use crate::error::DepthError;
+use crate::trades::TradeCorrelator;
+
+/// Messages buffered while the book syncs, older ones are dropped
+pub const DEFAULT_BUFFER_CAPACITY: usize = 30;
+
+/// Book update normalized from a venue message
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookUpdate {
+    /// Exchange time of the update in ms
+    pub event_time: i64,
+    pub first_update_id: i64,
+    pub last_update_id: i64,
+    /// Id the book must be at for this update to apply
+    pub previous_update_id: i64,
+    /// Zero amount removes the level
+    pub bids: Vec<DepthRow>,
+    pub asks: Vec<DepthRow>,
+    /// Checksum of the book once the update is applied, if the venue sends one
+    pub checksum: Option<u32>,
+}
+
+/// Message of a venue stream
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceMessage {
+    /// Whole book sent on the stream, replaces the book
+    Snapshot(BinanceSnapshot),
+    Update(BookUpdate),
+    /// Update kept as received, read once by `OrderBookSource::apply_text`
+    Text(String),
+}
+
+/// Update handled by `apply_update` or `OrderBookSource::apply_text`,
+/// the book only changed for `Sequence::Apply`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Applied {
+    pub event_time: i64,
+    pub first_update_id: i64,
+    pub last_update_id: i64,
+    pub previous_update_id: i64,
+    pub sequence: Sequence,
+}
+
+/// Parse error for a message which is valid JSON but not what the venue should send
+pub(crate) fn malformed(text: &str, msg: &str) -> DepthError {
+    DepthError::parse(text, serde::de::Error::custom(msg))
+}
+
+/// Levels sent as `(price, size)` decimal strings
+pub(crate) fn rows(text: &str, levels: impl IntoIterator<Item = (String, String)>) -> Result<Vec<DepthRow>, DepthError> {
+    levels.into_iter()
+        .map(|(price, size)| match (price.parse(), size.parse()) {
+            (Ok(price), Ok(amount)) => Ok(DepthRow { price, amount }),
+            _ => Err(malformed(text, "Bad number in level")),
+        })
+        .collect()
+}
+
+/// Where an update stands against the book
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sequence {
+    /// Directly follows the book
+    Apply,
+    /// Already in the book
+    Skip,
+    /// Updates are missing in between, a new snapshot is needed
+    Gap,
+}
+
+/// A venue the sync engine of `BinanceSpotOrderBook::sync_source` can run:
+/// where to stream updates from, how to read them, where to get a snapshot
+/// and how updates follow each other.
+pub trait OrderBookSource: Send + Sync + 'static {
+    /// Name of the update stream, used in logs and metrics labels
+    fn stream(&self) -> String;
+
+    fn stream_url(&self) -> Url;
+
+    /// Messages to send once connected, e.g. subscriptions
+    fn subscribe_messages(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Normalize a text message, `None` for messages which don't update the book
+    fn parse(&self, text: &str) -> Result<Option<SourceMessage>, DepthError>;
+
+    /// Read a message of the stream, by default with `parse`. Sources which can
+    /// apply levels straight from the text return it untouched as
+    /// `SourceMessage::Text`, to be read once by `apply_text`
+    fn parse_owned(&self, text: String) -> Result<Option<SourceMessage>, DepthError> {
+        self.parse(&text)
+    }
+
+    /// Sequence and apply a `SourceMessage::Text` update like `apply_update`,
+    /// `None` for messages which don't update the book
+    fn apply_text(
+        &self,
+        book: &mut Shared,
+        text: &str,
+        first: bool,
+        trades: Option<&mut TradeCorrelator>,
+    ) -> Result<Option<Applied>, DepthError>
+        where
+            Self: Sized
+    {
+        match self.parse(text)? {
+            Some(SourceMessage::Update(update)) => apply_update(self, book, update, first, trades).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether the venue sends snapshots on the stream (on every connection)
+    /// rather than through `fetch_snapshot`
+    fn snapshot_on_stream(&self) -> bool {
+        false
+    }
+
+    fn fetch_snapshot(&self) -> impl Future<Output = Result<BinanceSnapshot, DepthError>> + Send {
+        async { Err(DepthError::MissingSnapshot) }
+    }
+
+    fn buffer_capacity(&self) -> usize {
+        DEFAULT_BUFFER_CAPACITY
+    }
+
+    /// Levels kept per side, deeper ones are dropped after each update
+    fn max_depth(&self) -> Option<usize> {
+        None
+    }
+
+    /// Check `book` against the `checksum` of the update just applied
+    fn verify(&self, _book: &Shared, _checksum: u32) -> Result<(), DepthError> {
+        Ok(())
+    }
+
+    /// Whether `update` is the first to apply on a snapshot at `last_update_id`
+    fn match_snapshot(&self, update: &BookUpdate, last_update_id: i64) -> bool;
+
+    /// Where `update` stands against a book at `last_update_id`,
+    /// by default it must continue from `previous_update_id`
+    fn sequence(&self, update: &BookUpdate, last_update_id: i64) -> Sequence {
+        match update.previous_update_id.cmp(&last_update_id) {
+            std::cmp::Ordering::Equal => Sequence::Apply,
+            std::cmp::Ordering::Less => Sequence::Skip,
+            std::cmp::Ordering::Greater => Sequence::Gap,
+        }
+    }
+
+    /// Where `update` stands against a snapshot at `last_update_id`: `Apply` if it
+    /// is the first to apply, `Skip` if older, `Gap` if newer so the snapshot is stale
+    fn bootstrap(&self, update: &BookUpdate, last_update_id: i64) -> Sequence {
+        if self.match_snapshot(update, last_update_id) {
+            Sequence::Apply
+        } else if update.last_update_id <= last_update_id {
+            Sequence::Skip
+        } else {
+            Sequence::Gap
+        }
+    }
+}
+
+/// Sequence `update` against `book`, with `bootstrap` if `first` after a snapshot,
+/// and apply it with `apply_checked` if it follows. `trades` sees it before it changes the book.
+pub fn apply_update<S: OrderBookSource>(
+    source: &S,
+    book: &mut Shared,
+    update: BookUpdate,
+    first: bool,
+    trades: Option<&mut TradeCorrelator>,
+) -> Result<Applied, DepthError> {
+    let sequence = if first { source.bootstrap(&update, book.id()) } else { source.sequence(&update, book.id()) };
+    let applied = Applied {
+        event_time: update.event_time,
+        first_update_id: update.first_update_id,
+        last_update_id: update.last_update_id,
+        previous_update_id: update.previous_update_id,
+        sequence,
+    };
+    if sequence == Sequence::Apply {
+        if let Some(trades) = trades {
+            trades.on_update(book, &update);
+        }
+        apply_checked(source, book, update)?;
+    }
+    Ok(applied)
+}
+
+/// Apply `update` to `book`, trimmed to the depth of `source`
+/// and checked against the checksum of the update
+pub fn apply_checked<S: OrderBookSource>(source: &S, book: &mut Shared, update: BookUpdate) -> Result<(), DepthError> {
+    let checksum = update.checksum;
+    book.apply_update(update);
+    if let Some(depth) = source.max_depth() {
+        book.truncate(depth);
+    }
+    match checksum {
+        Some(checksum) => source.verify(book, checksum),
+        None => Ok(()),
+    }
+}
+
+/// Update of a replayed fixture, where it stood and the result of applying it
+#[cfg(test)]
+pub(crate) type Replayed = (Applied, Result<(), DepthError>);
+
+/// Replay a fixture of `source` messages, one per line: snapshots are loaded and
+/// given to `on_snapshot`, updates are applied with `apply_checked` when they follow
+#[cfg(test)]
+pub(crate) fn replay<S: OrderBookSource>(
+    source: &S,
+    fixture: &str,
+    mut on_snapshot: impl FnMut(&Shared),
+) -> (Shared, Vec<Replayed>) {
+    let mut book = Shared::new();
+    let mut updates = Vec::new();
+    for line in fixture.lines() {
+        match source.parse(line).unwrap() {
+            Some(SourceMessage::Snapshot(snapshot)) => {
+                book.load_snapshot(&snapshot);
+                on_snapshot(&book);
+            },
+            Some(SourceMessage::Update(update)) => {
+                let sequence = source.sequence(&update, book.id());
+                let applied = Applied {
+                    event_time: update.event_time,
+                    first_update_id: update.first_update_id,
+                    last_update_id: update.last_update_id,
+                    previous_update_id: update.previous_update_id,
+                    sequence,
+                };
+                let result = match sequence {
+                    Sequence::Apply => apply_checked(source, &mut book, update),
+                    Sequence::Skip | Sequence::Gap => Ok(()),
+                };
+                updates.push((applied, result));
+            },
+            _ => (),
+        }
+    }
+    (book, updates)
+}
+
+/// Check that `source` reads `text` as an error message of the venue
+#[cfg(test)]
+pub(crate) fn assert_venue_error<S: OrderBookSource>(source: &S, text: &str) {
+    match source.parse(text) {
+        Err(DepthError::Venue(_)) => (),
+        other => panic!("Expect a venue error, found {:?}", other),
+    }
+}
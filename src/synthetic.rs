@@ -0,0 +1,175 @@
+use crate::connection::BinanceSpotOrderBook;
+use crate::deep::{BinanceSpotOrderBookSnapshot, DepthRow};
+
+/// Levels per side of a synthetic book by default
+pub const DEFAULT_SYNTHETIC_DEPTH: usize = 20;
+
+/// Implied book of the cross `base/quote` (e.g. BNBBTC) from two books sharing a quote,
+/// `base_leg` quoting the base (BNBUSDT) and `quote_leg` quoting the quote (BTCUSDT).
+///
+/// Both ladders are walked together so each level can be executed on both legs:
+/// an implied bid sells base on the base leg bids and buys quote on the quote leg asks,
+/// an implied ask buys base on the base leg asks with quote sold on the quote leg bids.
+/// Amounts are in base asset. The synthetic book has no update id, `last_update_id` is 0
+/// and `time_stamp` the latest of the legs.
+pub fn synthetic_cross(
+    base_leg: &BinanceSpotOrderBookSnapshot,
+    quote_leg: &BinanceSpotOrderBookSnapshot,
+    depth: usize,
+) -> BinanceSpotOrderBookSnapshot {
+    BinanceSpotOrderBookSnapshot {
+        last_update_id: 0,
+        time_stamp: base_leg.time_stamp.max(quote_leg.time_stamp),
+        bids: walk(&base_leg.bids, &quote_leg.asks, depth),
+        asks: walk(&base_leg.asks, &quote_leg.bids, depth),
+    }
+}
+
+/// Implied levels trading `base` rows against `quote` rows, both in the common quote.
+/// A base row of `amount` at `price` is worth `amount * price`, which buys or sells
+/// `amount * price / quote.price` of the quote asset.
+fn walk(base: &[DepthRow], quote: &[DepthRow], depth: usize) -> Vec<DepthRow> {
+    let mut levels: Vec<DepthRow> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut base_left = base.first().map_or(0.0, |row| row.amount);
+    let mut quote_left = quote.first().map_or(0.0, |row| row.amount);
+    while i < base.len() && j < quote.len() {
+        let (base_row, quote_row) = (base[i], quote[j]);
+        let price = base_row.price / quote_row.price;
+        // Base the quote row left can be traded against
+        let quote_capacity = quote_left * quote_row.price / base_row.price;
+        let amount = base_left.min(quote_capacity);
+        if amount > 0.0 {
+            let len = levels.len();
+            match levels.last_mut() {
+                Some(last) if last.price == price => last.amount += amount,
+                _ if len < depth => levels.push(DepthRow { price, amount }),
+                _ => break,
+            }
+        }
+
+        // Move on from whichever row is used up, both when they match
+        if base_left <= quote_capacity {
+            i += 1;
+            base_left = base.get(i).map_or(0.0, |row| row.amount);
+            quote_left -= amount * base_row.price / quote_row.price;
+        } else {
+            base_left -= amount;
+        }
+        if quote_capacity <= amount {
+            j += 1;
+            quote_left = quote.get(j).map_or(0.0, |row| row.amount);
+        }
+    }
+    levels
+}
+
+/// Live synthetic book of a cross from the books of its two legs
+pub struct SyntheticCross<'a> {
+    base_leg: &'a BinanceSpotOrderBook,
+    quote_leg: &'a BinanceSpotOrderBook,
+    depth: usize,
+}
+
+impl<'a> SyntheticCross<'a> {
+    pub fn new(base_leg: &'a BinanceSpotOrderBook, quote_leg: &'a BinanceSpotOrderBook) -> Self {
+        Self::with_depth(base_leg, quote_leg, DEFAULT_SYNTHETIC_DEPTH)
+    }
+
+    pub fn with_depth(base_leg: &'a BinanceSpotOrderBook, quote_leg: &'a BinanceSpotOrderBook, depth: usize) -> Self {
+        SyntheticCross { base_leg, quote_leg, depth }
+    }
+
+    /// Implied book of the current legs, `None` until both are synced
+    pub async fn get_snapshot(&self) -> Option<BinanceSpotOrderBookSnapshot> {
+        let base_leg = self.base_leg.get_snapshot().await?;
+        let quote_leg = self.quote_leg.get_snapshot().await?;
+        Some(synthetic_cross(&base_leg, &quote_leg, self.depth))
+    }
+}
+
+/// How a direct book deviates from the synthetic book of the same cross,
+/// in basis points of the synthetic price. Positive means the direct book is higher.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CrossDeviation {
+    pub best_bid_bps: f64,
+    pub best_ask_bps: f64,
+    /// Average price to sell `amount` on the direct book against the synthetic one
+    pub sell_vwap_bps: Option<f64>,
+    /// Average price to buy `amount` on the direct book against the synthetic one
+    pub buy_vwap_bps: Option<f64>,
+    /// A book bids above the ask of the other, a triangular arbitrage
+    pub crossed: bool,
+}
+
+/// Average price of trading `amount` against `rows`, `None` if they are too thin
+pub fn vwap(rows: &[DepthRow], amount: f64) -> Option<f64> {
+    let mut left = amount;
+    let mut cost = 0.0;
+    for row in rows {
+        let traded = left.min(row.amount);
+        cost += traded * row.price;
+        left -= traded;
+        if left <= 0.0 {
+            return Some(cost / amount)
+        }
+    }
+    None
+}
+
+fn bps(direct: f64, synthetic: f64) -> f64 {
+    (direct - synthetic) / synthetic * 10_000.0
+}
+
+/// Compare the `direct` book of a cross with its `synthetic` book,
+/// average prices are for `amount` of base asset. `None` if a side is empty.
+pub fn cross_deviation(
+    direct: &BinanceSpotOrderBookSnapshot,
+    synthetic: &BinanceSpotOrderBookSnapshot,
+    amount: f64,
+) -> Option<CrossDeviation> {
+    let (direct_bid, direct_ask) = (direct.bids.first()?, direct.asks.first()?);
+    let (synthetic_bid, synthetic_ask) = (synthetic.bids.first()?, synthetic.asks.first()?);
+    let deviation = |direct: &[DepthRow], synthetic: &[DepthRow]| {
+        Some(bps(vwap(direct, amount)?, vwap(synthetic, amount)?))
+    };
+    Some(CrossDeviation {
+        best_bid_bps: bps(direct_bid.price, synthetic_bid.price),
+        best_ask_bps: bps(direct_ask.price, synthetic_ask.price),
+        sell_vwap_bps: deviation(&direct.bids, &synthetic.bids),
+        buy_vwap_bps: deviation(&direct.asks, &synthetic.asks),
+        crossed: direct_bid.price > synthetic_ask.price || synthetic_bid.price > direct_ask.price,
+    })
+}
+
+#[test]
+fn synthetic_cross_walks_both_legs(){
+    let snapshot = |time_stamp, bids: &[(f64, f64)], asks: &[(f64, f64)]| BinanceSpotOrderBookSnapshot {
+        time_stamp,
+        ..BinanceSpotOrderBookSnapshot::from_levels(bids, asks)
+    };
+    // BNBUSDT and BTCUSDT
+    let bnb = snapshot(10, &[(300.0, 2.0), (299.0, 5.0)], &[(301.0, 1.0), (302.0, 3.0)]);
+    let btc = snapshot(12, &[(30_000.0, 0.01), (29_900.0, 1.0)], &[(30_100.0, 0.01), (30_200.0, 1.0)]);
+    let cross = synthetic_cross(&bnb, &btc, DEFAULT_SYNTHETIC_DEPTH);
+    assert_eq!(cross.time_stamp, 12);
+
+    // 0.01 BTC at 30100 buys 301 USDT, about 1.0033 BNB at 300
+    let (first, second) = (cross.bids[0], cross.bids[1]);
+    assert_eq!(first.price, 300.0 / 30_100.0);
+    assert!((first.amount - 30_100.0 * 0.01 / 300.0).abs() < 1e-9);
+    assert_eq!(second.price, 300.0 / 30_200.0);
+    assert!((first.amount + second.amount - 2.0).abs() < 1e-9);
+    assert_eq!(cross.bids.iter().map(|row| row.amount).sum::<f64>().round(), 7.0);
+
+    // 0.01 BTC at 30000 only pays for about 0.9967 BNB at 301
+    assert_eq!(cross.asks[0].price, 301.0 / 30_000.0);
+    assert!((cross.asks[0].amount - 300.0 / 301.0).abs() < 1e-9);
+
+    let direct = snapshot(11, &[(0.00998, 1.0)], &[(0.01001, 1.0)]);
+    let deviation = cross_deviation(&direct, &cross, 1.0).unwrap();
+    assert!(deviation.best_bid_bps > 0.0 && deviation.best_ask_bps < 0.0);
+    assert!(deviation.sell_vwap_bps.is_some());
+    assert!(!deviation.crossed);
+    assert_eq!(cross_deviation(&direct, &cross, 100.0).unwrap().buy_vwap_bps, None);
+}
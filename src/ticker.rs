@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use serde::Deserialize;
+use crate::deep::{number, DepthRow, Shared};
+
+/// Tickers waiting for the diff book to reach their update id,
+/// the diff book is applied once a second while tickers come in real time
+pub const DEFAULT_PENDING_TICKERS: usize = 1000;
+
+/// `<symbol>@bookTicker` message: best bid and ask as of order book update `u`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct BookTicker {
+    #[serde(rename = "u")]
+    pub update_id: i64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "b", deserialize_with = "number")]
+    pub bid_price: f64,
+    #[serde(rename = "B", deserialize_with = "number")]
+    pub bid_qty: f64,
+    #[serde(rename = "a", deserialize_with = "number")]
+    pub ask_price: f64,
+    #[serde(rename = "A", deserialize_with = "number")]
+    pub ask_qty: f64,
+}
+
+impl BookTicker {
+    pub fn bid(&self) -> DepthRow {
+        DepthRow { price: self.bid_price, amount: self.bid_qty }
+    }
+
+    pub fn ask(&self) -> DepthRow {
+        DepthRow { price: self.ask_price, amount: self.ask_qty }
+    }
+}
+
+/// Top of the diff book disagreeing with bookTicker at the same update id
+#[derive(Debug, Clone, PartialEq)]
+pub struct TopOfBookMismatch {
+    pub update_id: i64,
+    pub ticker_bid: DepthRow,
+    pub ticker_ask: DepthRow,
+    pub book_bid: Option<DepthRow>,
+    pub book_ask: Option<DepthRow>,
+}
+
+/// Outcome of checking a ticker against the diff book
+#[derive(Debug, Clone, PartialEq)]
+pub enum TopCheck {
+    Match,
+    Mismatch(TopOfBookMismatch),
+    /// The book never was at the ticker update id, e.g. it falls
+    /// inside a depth event or is older than the book history
+    Unverified,
+}
+
+/// Compare `ticker` with the top of a book at the same update id
+pub fn compare_top(ticker: &BookTicker, book_bid: Option<DepthRow>, book_ask: Option<DepthRow>) -> TopCheck {
+    if book_bid == Some(ticker.bid()) && book_ask == Some(ticker.ask()) {
+        TopCheck::Match
+    } else {
+        TopCheck::Mismatch(TopOfBookMismatch {
+            update_id: ticker.update_id,
+            ticker_bid: ticker.bid(),
+            ticker_ask: ticker.ask(),
+            book_bid,
+            book_ask,
+        })
+    }
+}
+
+/// Hold bookTicker messages until the diff book has caught up with them,
+/// then check each against the book as it was at the ticker update id.
+/// Enable the book history to verify tickers older than the latest update.
+pub struct TickerChecker {
+    pending: VecDeque<BookTicker>,
+    capacity: usize,
+}
+
+impl Default for TickerChecker {
+    fn default() -> Self {
+        Self::new(DEFAULT_PENDING_TICKERS)
+    }
+}
+
+impl TickerChecker {
+    /// Keep at most `capacity` pending tickers, older ones are dropped
+    pub fn new(capacity: usize) -> Self {
+        TickerChecker { pending: VecDeque::new(), capacity }
+    }
+
+    pub fn push(&mut self, ticker: BookTicker) {
+        if self.pending.len() == self.capacity {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(ticker);
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Check the tickers `book` has caught up with
+    pub fn check(&mut self, book: &Shared) -> Vec<(BookTicker, TopCheck)> {
+        let mut checks = Vec::new();
+        while let Some(ticker) = self.pending.front() {
+            if ticker.update_id > book.id() {
+                break
+            }
+            let ticker = self.pending.pop_front().unwrap();
+            let check = if ticker.update_id == book.id() {
+                compare_top(&ticker, book.top_bids(1).pop(), book.top_asks(1).pop())
+            } else {
+                match book.history().at_update_id(ticker.update_id) {
+                    Some(state) if state.last_update_id == ticker.update_id => {
+                        compare_top(&ticker, state.bids.first().copied(), state.asks.first().copied())
+                    },
+                    _ => TopCheck::Unverified,
+                }
+            };
+            checks.push((ticker, check));
+        }
+        checks
+    }
+
+    /// Drop pending tickers, e.g. when the book resyncs
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[test]
+fn ticker_checks_at_equal_update_ids(){
+    use crate::deep::Event;
+
+    let ticker = |text: &str| serde_json::from_str::<BookTicker>(text).unwrap();
+    let mut book = Shared::with_history(8);
+    let mut checker = TickerChecker::default();
+    book.add_event(serde_json::from_str::<Event>(r#"{"e":"depthUpdate","E":1,"s":"BNBBTC","U":1,"u":10,"b":[["0.0024","10"]],"a":[["0.0026","100"]]}"#).unwrap());
+
+    checker.push(ticker(r#"{"u":10,"s":"BNBBTC","b":"0.00240000","B":"10.00000000","a":"0.00260000","A":"100.00000000"}"#));
+    checker.push(ticker(r#"{"u":11,"s":"BNBBTC","b":"0.00240000","B":"10.00000000","a":"0.00250000","A":"1.00000000"}"#));
+    checker.push(ticker(r#"{"u":13,"s":"BNBBTC","b":"0.00240000","B":"9.00000000","a":"0.00250000","A":"1.00000000"}"#));
+    checker.push(ticker(r#"{"u":14,"s":"BNBBTC","b":"0.00240000","B":"10.00000000","a":"0.00250000","A":"1.00000000"}"#));
+    assert_eq!(checker.check(&book), vec![(ticker(r#"{"u":10,"s":"BNBBTC","b":"0.0024","B":"10","a":"0.0026","A":"100"}"#), TopCheck::Match)]);
+
+    book.add_event(serde_json::from_str::<Event>(r#"{"e":"depthUpdate","E":2,"s":"BNBBTC","U":11,"u":13,"b":[],"a":[["0.0025","1"]]}"#).unwrap());
+    let checks: Vec<TopCheck> = checker.check(&book).into_iter().map(|(_, check)| check).collect();
+    assert_eq!(checks.len(), 2);
+    // 11 falls inside the event
+    assert_eq!(checks[0], TopCheck::Unverified);
+    assert!(matches!(&checks[1], TopCheck::Mismatch(mismatch) if mismatch.book_bid == Some(DepthRow { price: 0.0024, amount: 10.0 })));
+    assert_eq!(checker.pending(), 1);
+}
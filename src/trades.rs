@@ -0,0 +1,298 @@
+use std::collections::{BTreeMap, VecDeque};
+use ordered_float::OrderedFloat;
+use serde::Deserialize;
+use crate::deep::{number, DepthRow, Shared};
+use crate::source::BookUpdate;
+
+/// Off book trades kept for inspection
+const OFF_BOOK_CAPACITY: usize = 100;
+/// Trades waiting for the depth update they belong to
+const PENDING_TRADES: usize = 10_000;
+
+/// Binance trade stream of a symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TradeStream {
+    /// Every trade
+    Trade,
+    /// Trades of one taker order at one price aggregated
+    AggTrade,
+}
+
+impl TradeStream {
+    pub fn name(self) -> &'static str {
+        match self {
+            TradeStream::Trade => "trade",
+            TradeStream::AggTrade => "aggTrade",
+        }
+    }
+}
+
+/// `trade` or `aggTrade` message
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Trade {
+    /// Trade id, or aggregate trade id
+    #[serde(rename = "t", alias = "a")]
+    pub trade_id: i64,
+    #[serde(rename = "p", deserialize_with = "number")]
+    pub price: f64,
+    #[serde(rename = "q", deserialize_with = "number")]
+    pub qty: f64,
+    #[serde(rename = "T")]
+    pub trade_time: i64,
+    /// The buyer was the maker, the trade took liquidity from the bids
+    #[serde(rename = "m")]
+    pub buyer_maker: bool,
+}
+
+/// Side of the book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+impl Trade {
+    /// Side the resting order was on
+    pub fn maker_side(&self) -> Side {
+        if self.buyer_maker { Side::Bid } else { Side::Ask }
+    }
+}
+
+/// Where the quantity removed from a level went
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LevelStats {
+    /// Quantity removed by trades
+    pub filled: f64,
+    /// Quantity removed without a trade
+    pub cancelled: f64,
+    /// Updates where the level lost quantity to trades
+    pub fills: u64,
+    /// Updates where the level lost quantity otherwise
+    pub cancels: u64,
+}
+
+impl LevelStats {
+    fn add(&mut self, other: &LevelStats) {
+        self.filled += other.filled;
+        self.cancelled += other.cancelled;
+        self.fills += other.fills;
+        self.cancels += other.cancels;
+    }
+}
+
+/// Trade that printed at a price the book didn't show on the maker side
+#[derive(Debug, Clone, PartialEq)]
+pub struct OffBookTrade {
+    pub trade: Trade,
+    /// Id of the book the trade was checked against
+    pub update_id: i64,
+}
+
+/// Attribute the quantity each depth update removes from a level to the trades
+/// printed at that level since the previous update, the rest to cancellations.
+/// Trades are matched to the first update with an event time at or after them.
+#[derive(Default)]
+pub struct TradeCorrelator {
+    pending: VecDeque<Trade>,
+    levels: BTreeMap<(Side, OrderedFloat<f64>), LevelStats>,
+    off_book: VecDeque<OffBookTrade>,
+    off_book_count: u64,
+    /// Trades of the update being attributed
+    current: Vec<Trade>,
+    /// Levels the update being attributed sets
+    touched: Vec<(Side, f64)>,
+}
+
+impl TradeCorrelator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn on_trade(&mut self, trade: Trade) {
+        if self.pending.len() == PENDING_TRADES {
+            self.pending.pop_front();
+        }
+        self.pending.push_back(trade);
+    }
+
+    /// Attribute the level decreases of `update`, to call with `book` as it was
+    /// before `update` is applied
+    pub fn on_update(&mut self, book: &Shared, update: &BookUpdate) {
+        self.start_update(update.event_time);
+        for (side, rows) in [(Side::Bid, &update.bids), (Side::Ask, &update.asks)] {
+            for row in rows {
+                let before = match side {
+                    Side::Bid => book.bid_amount(row.price),
+                    Side::Ask => book.ask_amount(row.price),
+                };
+                self.on_level(side, row, before);
+            }
+        }
+        self.finish_update(book);
+    }
+
+    /// Start attributing an update sent at `event_time`, its levels are then
+    /// given one by one to `on_level` and `finish_update` ends it
+    pub(crate) fn start_update(&mut self, event_time: i64) {
+        self.current.clear();
+        self.touched.clear();
+        while let Some(trade) = self.pending.front() {
+            if trade.trade_time > event_time {
+                break
+            }
+            self.current.push(self.pending.pop_front().unwrap());
+        }
+    }
+
+    /// Attribute the decrease of a level set to `row`, from `before` in the book
+    pub(crate) fn on_level(&mut self, side: Side, row: &DepthRow, before: Option<f64>) {
+        self.touched.push((side, row.price));
+        let decrease = before.unwrap_or(0.0) - row.amount;
+        if decrease <= 0.0 {
+            return
+        }
+        let traded: f64 = self.current.iter()
+            .filter(|trade| trade.maker_side() == side && trade.price == row.price)
+            .map(|trade| trade.qty)
+            .sum();
+        let filled = traded.min(decrease);
+        let stats = self.levels.entry((side, OrderedFloat(row.price))).or_default();
+        stats.add(&LevelStats {
+            filled,
+            cancelled: decrease - filled,
+            fills: (filled > 0.0) as u64,
+            cancels: (decrease > filled) as u64,
+        });
+    }
+
+    /// Record the trades of the update at prices neither in `book` nor set by the update,
+    /// `book` may be taken before or after the levels are applied
+    pub(crate) fn finish_update(&mut self, book: &Shared) {
+        for trade in std::mem::take(&mut self.current) {
+            let side = trade.maker_side();
+            let in_book = match side {
+                Side::Bid => book.bid_amount(trade.price).is_some(),
+                Side::Ask => book.ask_amount(trade.price).is_some(),
+            };
+            if !in_book && !self.touched.contains(&(side, trade.price)) {
+                self.off_book_count += 1;
+                if self.off_book.len() == OFF_BOOK_CAPACITY {
+                    self.off_book.pop_front();
+                }
+                self.off_book.push_back(OffBookTrade { trade, update_id: book.id() });
+            }
+        }
+    }
+
+    /// Statistics of the level at `price` on `side`
+    pub fn level(&self, side: Side, price: f64) -> Option<LevelStats> {
+        self.levels.get(&(side, OrderedFloat(price))).copied()
+    }
+
+    /// Statistics of every level of `side`, lowest price first
+    pub fn levels(&self, side: Side) -> Vec<(f64, LevelStats)> {
+        self.levels.iter()
+            .filter(|((level_side, _), _)| *level_side == side)
+            .map(|((_, price), stats)| (price.into_inner(), *stats))
+            .collect()
+    }
+
+    /// Statistics of all levels together
+    pub fn totals(&self) -> LevelStats {
+        let mut totals = LevelStats::default();
+        for stats in self.levels.values() {
+            totals.add(stats);
+        }
+        totals
+    }
+
+    /// Number of trades at prices absent from the book
+    pub fn off_book_count(&self) -> u64 {
+        self.off_book_count
+    }
+
+    /// Latest trades at prices absent from the book, oldest first
+    pub fn off_book(&self) -> Vec<OffBookTrade> {
+        self.off_book.iter().cloned().collect()
+    }
+}
+
+#[test]
+fn trades_split_fills_and_cancels(){
+    use crate::deep::BinanceSnapshot;
+
+    let mut book = Shared::new();
+    book.load_snapshot(&BinanceSnapshot {
+        last_update_id: 10,
+        bids: vec![DepthRow { price: 99.0, amount: 5.0 }, DepthRow { price: 98.0, amount: 2.0 }],
+        asks: vec![DepthRow { price: 101.0, amount: 3.0 }],
+    });
+    let trade = |text: &str| serde_json::from_str::<Trade>(text).unwrap();
+    let mut correlator = TradeCorrelator::new();
+    correlator.on_trade(trade(r#"{"e":"trade","E":1001,"s":"BNBBTC","t":1,"p":"99.0","q":"1.5","T":1000,"m":true,"M":true}"#));
+    correlator.on_trade(trade(r#"{"e":"aggTrade","E":1002,"s":"BNBBTC","a":2,"p":"97.0","q":"0.5","f":3,"l":3,"T":1001,"m":true,"M":true}"#));
+    correlator.on_trade(trade(r#"{"e":"trade","E":1201,"s":"BNBBTC","t":4,"p":"101.0","q":"1","T":1200,"m":false,"M":true}"#));
+
+    let update = BookUpdate {
+        event_time: 1100,
+        first_update_id: 11,
+        last_update_id: 12,
+        previous_update_id: 10,
+        // 99 lost 1.5 traded and 0.5 cancelled, 98 was cancelled
+        bids: vec![DepthRow { price: 99.0, amount: 3.0 }, DepthRow { price: 98.0, amount: 0.0 }],
+        asks: vec![DepthRow { price: 101.0, amount: 4.0 }],
+        checksum: None,
+    };
+    correlator.on_update(&book, &update);
+
+    assert_eq!(correlator.level(Side::Bid, 99.0), Some(LevelStats { filled: 1.5, cancelled: 0.5, fills: 1, cancels: 1 }));
+    assert_eq!(correlator.level(Side::Bid, 98.0), Some(LevelStats { filled: 0.0, cancelled: 2.0, fills: 0, cancels: 1 }));
+    assert_eq!(correlator.level(Side::Ask, 101.0), None);
+    assert_eq!(correlator.totals().cancelled, 2.5);
+    // No bid at 97
+    assert_eq!(correlator.off_book_count(), 1);
+    assert_eq!(correlator.off_book()[0].trade.trade_id, 2);
+}
+
+#[test]
+fn trades_from_text_levels(){
+    use crate::deep::{BinanceSnapshot, Event};
+    use crate::source::Sequence;
+
+    let snapshot = BinanceSnapshot {
+        last_update_id: 10,
+        bids: vec![DepthRow { price: 99.0, amount: 5.0 }],
+        asks: vec![DepthRow { price: 101.0, amount: 3.0 }],
+    };
+    let trade = |text: &str| serde_json::from_str::<Trade>(text).unwrap();
+    let text = r#"{"e":"depthUpdate","E":1100,"s":"BNBBTC","U":11,"u":12,"b":[["99.0","3.0"]],"a":[["100.5","1.0"]]}"#;
+
+    let mut owned = TradeCorrelator::new();
+    let mut borrowed = TradeCorrelator::new();
+    for correlator in [&mut owned, &mut borrowed] {
+        correlator.on_trade(trade(r#"{"e":"trade","E":1001,"s":"BNBBTC","t":1,"p":"99.0","q":"1.5","T":1000,"m":true,"M":true}"#));
+        // Added and taken within the update
+        correlator.on_trade(trade(r#"{"e":"trade","E":1002,"s":"BNBBTC","t":2,"p":"100.5","q":"0.5","T":1001,"m":false,"M":true}"#));
+        correlator.on_trade(trade(r#"{"e":"trade","E":1003,"s":"BNBBTC","t":3,"p":"97.0","q":"0.5","T":1002,"m":true,"M":true}"#));
+    }
+
+    let mut book = Shared::new();
+    book.load_snapshot(&snapshot);
+    let event: Event = serde_json::from_str(text).unwrap();
+    owned.on_update(&book, &BookUpdate {
+        event_time: event.ts,
+        first_update_id: 11,
+        last_update_id: 12,
+        previous_update_id: 10,
+        bids: event.bids,
+        asks: event.asks,
+        checksum: None,
+    });
+    let (_, sequence) = book.apply_event_text(text, |_, _| Sequence::Apply, Some(&mut borrowed)).unwrap();
+
+    assert_eq!(sequence, Sequence::Apply);
+    assert_eq!(borrowed.levels(Side::Bid), owned.levels(Side::Bid));
+    assert_eq!(borrowed.level(Side::Bid, 99.0), Some(LevelStats { filled: 1.5, cancelled: 0.5, fills: 1, cancels: 1 }));
+    assert_eq!(borrowed.off_book(), owned.off_book());
+    assert_eq!(borrowed.off_book().iter().map(|off| (off.trade.trade_id, off.update_id)).collect::<Vec<_>>(), vec![(3, 10)]);
+}